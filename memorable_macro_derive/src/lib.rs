@@ -6,7 +6,7 @@ use syn::Ident;
 #[doc = r#"# MemoDoc
 Implements the `MemoDoc` trait to struct.
 Struct must have field uuid to derive this trait.
-```
+```ignore
 impl MemoDoc for #doc {
     fn get_id(&self) -> &str {
         &self.uuid
@@ -0,0 +1,23 @@
+/// One operation of a batch, see [`DataBase::apply`](crate::DataBase::apply).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op<T> {
+    /// Adds the document like [`DataBase::push`](crate::DataBase::push), failing if its id exists.
+    Push(T),
+    /// Replaces the document with its id like [`DataBase::replace`](crate::DataBase::replace), failing if there is none.
+    Replace(T),
+    /// Adds or replaces the document like [`DataBase::upsert`](crate::DataBase::upsert).
+    Upsert(T),
+    /// Deletes the document with this id like [`DataBase::del`](crate::DataBase::del), failing if there is none.
+    Del(String),
+}
+
+/// What a batch does with its valid operations when others fail, see [`DataBase::apply`](crate::DataBase::apply).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatchMode {
+    /// The valid operations are written, the failed ones skipped.
+    #[default]
+    Partial,
+    /// Nothing is written unless every operation is valid. The valid ones then fail with
+    /// [`MemoError::Aborted`](crate::MemoError::Aborted).
+    AllOrNothing,
+}
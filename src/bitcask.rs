@@ -0,0 +1,673 @@
+use std::{collections::{BTreeMap, HashMap}, fs::{self, File}, hash::{DefaultHasher, Hash, Hasher}, io::{self, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write}, path::PathBuf, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, MutexGuard}, thread};
+
+use crate::{disk::{self, SyncLevel}, lock, LockGuard, LockMode, MemoError, Storage, Usage};
+
+/// Bytes before the key of every record: crc32, key length and value length, all little endian u32.
+const HEADER: usize = 12;
+/// Value length marking a tombstone, which has no value bytes.
+const TOMBSTONE: u32 = u32::MAX;
+/// Bytes before the key of every hint: key length, value length and record offset.
+const HINT_HEADER: usize = 16;
+
+/// Where the record holding a live key's value starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Entry {
+    segment: u32,
+    offset: u64,
+    len: u32,
+}
+
+impl Entry {
+    fn size(&self, key: &str) -> usize {
+        HEADER + key.len() + self.len as usize
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    keydir: BTreeMap<String, Entry>,
+    /// Every segment by id, the last being the active one appended to.
+    segments: BTreeMap<u32, File>,
+    /// Where the valid records of the active segment end. A torn record past it is cut off by the next append.
+    active_len: u64,
+    /// Length of the active segment's file as last seen.
+    file_len: u64,
+    /// Bytes taken by the records the keydir points at.
+    live: u64,
+    /// Which load this is, so a merge notices that the keydir it copied from was replaced.
+    epoch: u64,
+}
+
+impl State {
+    /// Points `key` at `entry`, or drops it for `None`, keeping [`State::live`] up to date.
+    fn set(&mut self, key: &str, entry: Option<Entry>) {
+        let previous: Option<Entry> = match entry {
+            Some(entry) => {
+                self.live += entry.size(key) as u64;
+                self.keydir.insert(key.to_string(), entry)
+            },
+            None => self.keydir.remove(key),
+        };
+        if let Some(previous) = previous {
+            self.live -= previous.size(key) as u64;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    /// `None` until the segments are first read, and again after a failed refresh.
+    state: Mutex<Option<State>>,
+    loads: AtomicU64,
+    /// Held for the whole of a merge, so only one runs at a time.
+    merging: Mutex<()>,
+}
+
+#[doc = r#"A log-structured [`Storage`] in the style of [Bitcask](https://riak.com/assets/bitcask-intro.pdf),
+used by [`StorageMode::Bitcask`](crate::StorageMode::Bitcask).
+
+Every write or removal appends one binary record (crc32, key and value lengths, key, value) to the
+active segment file `<dir>/<id>.data`. An in-memory keydir maps every key to the offset of its
+latest record, so a read is a single seek and read. Once the active segment grows past
+[`BitcaskStorage::segment_size`] the next write starts a new one, and once [`BitcaskStorage::merge_after`]
+older segments pile up a background thread merges them into one holding only live records, plus a
+`<id>.hint` file listing its keys so the next open doesn't have to scan it. A merge can also be
+run on demand with [`DataBase::compact`](crate::DataBase::compact).
+
+Opening reads the hint files, and scans the segments that have none. A record torn by a crash at
+the end of the active segment, cut short or failing its checksum with no intact record after it,
+is ignored and cut off by the next write. A damaged record anywhere else fails with
+[`MemoError::Corrupted`], which [`CorruptionPolicy::Backup`](crate::CorruptionPolicy::Backup)
+answers by copying the segment aside and keeping the records before the damage. The
+`<id>.data.merging` output of a merge interrupted by a crash is removed.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{BitcaskStorage, Config, DataBase, MemoDoc, StorageMode};
+use memorable_macro_derive::MemoDoc;
+use std::sync::Arc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String,
+    done: bool
+}
+
+fn main() {
+    # let dir = std::env::temp_dir().join("memorable_doc_bitcask");
+    # let _ = std::fs::remove_dir_all(&dir);
+    # pollster::block_on(async {
+    let storage = BitcaskStorage::new(&dir).segment_size(64).merge_after(usize::MAX);
+    let config = Config { mode: StorageMode::Bitcask, ..Config::default() };
+    let mut f: DataBase<Task> = DataBase::open_with_storage(Arc::new(storage), config.clone()).unwrap();
+    for i in 0..10 {
+        f.push(Task { uuid: i.to_string(), done: false }).await.unwrap();
+        f.del(&i.to_string()).await.unwrap();
+    }
+    f.push(Task { uuid: "kept".into(), done: true }).await.unwrap();
+    let segments = || std::fs::read_dir(&dir).unwrap().filter(|e| e.as_ref().unwrap().path().extension().unwrap() == "data").count();
+    assert!(segments() > 5);
+
+    f.compact().unwrap();
+    assert_eq!(segments(), 2);
+    let f: DataBase<Task> = DataBase::open_with(dir.to_str().unwrap(), config).unwrap();
+    assert_eq!(f.docs.len(), 1);
+    assert!(f.docs["kept"].done);
+    # });
+}
+```"#]
+#[derive(Debug, Clone)]
+pub struct BitcaskStorage {
+    dir: PathBuf,
+    segment_size: u64,
+    merge_after: usize,
+    shared: Arc<Shared>,
+}
+
+impl BitcaskStorage {
+    /// A storage in `dir`, which is created by the first write, with 64 MiB segments merged once 4 of them are full.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), segment_size: 64 * 1024 * 1024, merge_after: 4, shared: Arc::default() }
+    }
+
+    /// Starts a new segment once the active one reaches `bytes`.
+    pub fn segment_size(mut self, bytes: u64) -> Self {
+        self.segment_size = bytes;
+        self
+    }
+
+    /// Merges the full segments in the background once there are `segments` of them.
+    pub fn merge_after(mut self, segments: usize) -> Self {
+        self.merge_after = segments;
+        self
+    }
+
+    fn path(&self, id: u32, extension: &str) -> PathBuf {
+        self.dir.join(format!("{id:06}.{extension}"))
+    }
+
+    /// Ids of the segments on disk, sorted.
+    fn segment_ids(&self) -> io::Result<Vec<u32>> {
+        let entries: fs::ReadDir = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut ids: Vec<u32> = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            if let Some(id) = name.to_str().and_then(|n| n.strip_suffix(".data")).and_then(|n| n.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, Option<State>> {
+        // A poisoned state is dropped and read again from disk.
+        self.shared.state.lock().unwrap_or_else(|e| {
+            let mut guard = e.into_inner();
+            *guard = None;
+            guard
+        })
+    }
+
+    /// Runs `f` on the state, reading it from disk first if it wasn't yet, or with `refresh` if
+    /// another handle changed the segments since.
+    fn with_state<R>(&self, refresh: bool, f: impl FnOnce(&mut State) -> io::Result<R>) -> io::Result<R> {
+        let mut guard: MutexGuard<'_, Option<State>> = self.lock_state();
+        let state: &mut State = match guard.take() {
+            Some(state) if !(refresh && self.changed(&state)?) => guard.insert(state),
+            _ => guard.insert(self.load()?),
+        };
+        f(state)
+    }
+
+    /// Whether the segments on disk are not the ones `state` was read from and written to.
+    fn changed(&self, state: &State) -> io::Result<bool> {
+        if !self.segment_ids()?.iter().eq(state.segments.keys()) {
+            return Ok(true);
+        }
+        match state.segments.last_key_value() {
+            Some((_, file)) => Ok(file.metadata()?.len() != state.file_len),
+            None => Ok(false),
+        }
+    }
+
+    /// Builds the keydir from the hint file of every segment that has one, scanning the others.
+    fn load(&self) -> io::Result<State> {
+        self.remove_merging()?;
+        let ids: Vec<u32> = self.segment_ids()?;
+        let mut state = State {
+            keydir: BTreeMap::new(),
+            segments: BTreeMap::new(),
+            active_len: 0,
+            file_len: 0,
+            live: 0,
+            epoch: self.shared.loads.fetch_add(1, Ordering::SeqCst),
+        };
+        for (i, &id) in ids.iter().enumerate() {
+            let path: PathBuf = self.path(id, "data");
+            let file: File = File::options().read(true).append(true).open(&path)?;
+            let active: bool = i + 1 == ids.len();
+            // The active segment is never a merge's output, so it has no hint.
+            let hints: Option<Vec<(String, Entry)>> = match active {
+                true => None,
+                false => disk::read(&self.path(id, "hint"))?.and_then(|b| parse_hints(&b, id)),
+            };
+            match hints {
+                Some(hints) => hints.into_iter().for_each(|(key, entry)| state.set(&key, Some(entry))),
+                None => {
+                    let bytes: Vec<u8> = fs::read(&path)?;
+                    let valid: usize = scan(&bytes, id, active, &mut state).map_err(|(_, reason)| corrupted(path.clone(), reason))?;
+                    if active {
+                        state.active_len = valid as u64;
+                        state.file_len = bytes.len() as u64;
+                    }
+                },
+            }
+            state.segments.insert(id, file);
+        }
+        Ok(state)
+    }
+
+    /// Removes the output of merges a crash interrupted. A merge running in this process is left
+    /// alone; one running in another process fails to swap in its output and is retried later.
+    fn remove_merging(&self) -> io::Result<()> {
+        let Ok(_merging) = self.shared.merging.try_lock() else {
+            return Ok(());
+        };
+        let entries: fs::ReadDir = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path: PathBuf = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "merging") {
+                disk::remove(&path, SyncLevel::None)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the first scanned segment that is damaged aside and cuts it back to the records
+    /// before the damage, so that the next load keeps what could be read.
+    fn move_damaged_aside(&self, suffix: &str) -> io::Result<PathBuf> {
+        let mut guard: MutexGuard<'_, Option<State>> = self.lock_state();
+        *guard = None;
+        let ids: Vec<u32> = self.segment_ids()?;
+        for (i, &id) in ids.iter().enumerate() {
+            let active: bool = i + 1 == ids.len();
+            if !active && disk::read(&self.path(id, "hint"))?.and_then(|b| parse_hints(&b, id)).is_some() {
+                continue;
+            }
+            let path: PathBuf = self.path(id, "data");
+            let bytes: Vec<u8> = fs::read(&path)?;
+            let mut scratch = State { keydir: BTreeMap::new(), segments: BTreeMap::new(), active_len: 0, file_len: 0, live: 0, epoch: 0 };
+            let Err((valid, _)) = scan(&bytes, id, active, &mut scratch) else {
+                continue;
+            };
+            let dest: PathBuf = disk::backup_copy(&path, suffix)?;
+            let file: File = File::options().write(true).open(&path)?;
+            file.set_len(valid as u64)?;
+            file.sync_all()?;
+            return Ok(dest);
+        }
+        Err(io::Error::new(ErrorKind::NotFound, "no damaged segment to move aside"))
+    }
+
+    /// Appends the record putting `value` under `key`, or removing it for `None`.
+    ///
+    /// A full active segment is only replaced here, before the record is written, so that failing
+    /// to start the next one fails the write instead of a write that already landed.
+    fn append_record(&self, state: &mut State, key: &str, value: Option<&[u8]>, level: SyncLevel) -> io::Result<()> {
+        if key.is_empty() {
+            // Stands for the whole storage, and an empty key marks a zeroed record when scanning.
+            return Err(io::Error::new(ErrorKind::InvalidInput, "bitcask keys can't be empty"));
+        }
+        match state.segments.last_key_value() {
+            None => {
+                fs::create_dir_all(&self.dir)?;
+                self.start_segment(state, 1, level)?;
+            },
+            Some((&id, _)) if state.active_len >= self.segment_size => {
+                // Ids step by two, leaving room below every active segment for a merge's output.
+                self.start_segment(state, id + 2, level)?;
+                if state.segments.len() > self.merge_after {
+                    let storage: BitcaskStorage = self.clone();
+                    // A failed merge leaves the segments as they were, it is retried after the next
+                    // rollover, and `compact` merges in the foreground where its errors are returned.
+                    thread::spawn(move || storage.merge(true));
+                }
+            },
+            Some(_) => {},
+        }
+        let Some((&id, file)) = state.segments.last_key_value() else {
+            unreachable!("a segment was just started");
+        };
+        if state.file_len != state.active_len {
+            file.set_len(state.active_len)?;
+            state.file_len = state.active_len;
+        }
+        let record: Vec<u8> = encode(key, value);
+        if let Err(e) = (&*file).write_all(&record).and_then(|_| level.sync_file(file)) {
+            // Whatever part was written is cut off by the next append.
+            state.file_len = u64::MAX;
+            return Err(e);
+        }
+        let entry = Entry { segment: id, offset: state.active_len, len: value.map_or(0, |v| v.len() as u32) };
+        state.active_len += record.len() as u64;
+        state.file_len = state.active_len;
+        state.set(key, value.map(|_| entry));
+        Ok(())
+    }
+
+    fn start_segment(&self, state: &mut State, id: u32, level: SyncLevel) -> io::Result<()> {
+        let path: PathBuf = self.path(id, "data");
+        let file: File = File::options().read(true).append(true).create_new(true).open(&path)?;
+        level.sync_dir(&path)?;
+        state.segments.insert(id, file);
+        state.active_len = 0;
+        state.file_len = 0;
+        Ok(())
+    }
+
+    /// Rewrites every segment but the active one into a single segment with a hint file, keeping
+    /// only the records of live keys.
+    ///
+    /// In the `background` a merge already running is left to finish, the active segment is kept,
+    /// and the merge is dropped if the database lock is taken. Otherwise the caller holds that lock
+    /// and the active segment is closed first, so everything is merged.
+    fn merge(&self, background: bool) -> io::Result<()> {
+        let _merging: MutexGuard<'_, ()> = match background {
+            true => match self.shared.merging.try_lock() {
+                Ok(guard) => guard,
+                Err(_) => return Ok(()),
+            },
+            false => self.shared.merging.lock().unwrap_or_else(|e| e.into_inner()),
+        };
+
+        let planned = self.with_state(!background, |state| {
+            if !background && state.active_len > 0 {
+                if let Some((&id, _)) = state.segments.last_key_value() {
+                    self.start_segment(state, id + 2, SyncLevel::All)?;
+                }
+            }
+            let Some((&active, _)) = state.segments.last_key_value() else {
+                return Ok(None);
+            };
+            let inputs: Vec<u32> = state.segments.range(..active).map(|(&id, _)| id).collect();
+            // Only the output of the last merge, which holds no dead records.
+            if inputs.is_empty() || inputs == [active - 1] {
+                return Ok(None);
+            }
+            let mut files: HashMap<u32, File> = HashMap::new();
+            for id in &inputs {
+                files.insert(*id, state.segments[id].try_clone()?);
+            }
+            let live: Vec<(String, Entry)> = state.keydir.iter().filter(|(_, e)| e.segment < active).map(|(k, e)| (k.clone(), *e)).collect();
+            Ok(Some((active - 1, state.epoch, inputs, files, live)))
+        })?;
+        let Some((output, epoch, inputs, files, live)) = planned else {
+            return Ok(());
+        };
+
+        let tmp: PathBuf = self.dir.join(format!("{output:06}.data.merging"));
+        let mut out: BufWriter<File> = BufWriter::new(File::create(&tmp)?);
+        let mut hints: Vec<u8> = Vec::new();
+        let mut moved: Vec<(String, Entry, Entry)> = Vec::with_capacity(live.len());
+        let mut offset: u64 = 0;
+        let copied: io::Result<()> = (|| {
+            for (key, entry) in live {
+                let record: Vec<u8> = read_record(&files[&entry.segment], &key, &entry, &self.path(entry.segment, "data"))?;
+                out.write_all(&record)?;
+                let merged = Entry { segment: output, offset, len: entry.len };
+                push_hint(&mut hints, &key, &merged);
+                offset += record.len() as u64;
+                moved.push((key, entry, merged));
+            }
+            out.flush()?;
+            out.get_ref().sync_all()
+        })();
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        hints.extend_from_slice(&crc32fast::hash(&hints).to_le_bytes());
+
+        // Other processes only see the output once the lock keeps them from writing.
+        let _lock: Option<LockGuard> = match background {
+            true => match self.lock(true, LockMode::Try) {
+                Ok(guard) => guard,
+                Err(_) => {
+                    let _ = fs::remove_file(&tmp);
+                    return Ok(());
+                },
+            },
+            false => None,
+        };
+        let swapped: io::Result<bool> = self.with_state(false, |state| {
+            if state.epoch != epoch || self.changed(state)? {
+                return Ok(false);
+            }
+            let path: PathBuf = self.path(output, "data");
+            fs::rename(&tmp, &path)?;
+            disk::write_atomic(&self.path(output, "hint"), &hints, SyncLevel::All)?;
+            state.segments.insert(output, File::options().read(true).append(true).open(&path)?);
+            for (key, old, merged) in moved {
+                if state.keydir.get(&key) == Some(&old) {
+                    state.keydir.insert(key, merged);
+                }
+            }
+            for id in inputs {
+                state.segments.remove(&id);
+                fs::remove_file(self.path(id, "data"))?;
+                disk::remove(&self.path(id, "hint"), SyncLevel::None)?;
+            }
+            disk::sync_dir(&path)?;
+            Ok(true)
+        });
+        if !matches!(swapped, Ok(true)) {
+            let _ = fs::remove_file(&tmp);
+        }
+        swapped.map(|_| ())
+    }
+
+    /// The record of `key` as stored, without checking it.
+    fn raw(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.with_state(false, |state| {
+            let Some(entry) = state.keydir.get(key) else {
+                return Ok(None);
+            };
+            let mut record: Vec<u8> = vec![0; entry.size(key)];
+            let mut file: &File = &state.segments[&entry.segment];
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.read_exact(&mut record)?;
+            Ok(Some(record))
+        })
+    }
+}
+
+impl Storage for BitcaskStorage {
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.with_state(false, |state| {
+            let Some(entry) = state.keydir.get(key) else {
+                return Ok(None);
+            };
+            let record: Vec<u8> = read_record(&state.segments[&entry.segment], key, entry, &self.path(entry.segment, "data"))?;
+            Ok(Some(record[HEADER + key.len()..].to_vec()))
+        })
+    }
+
+    fn write(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+        self.with_state(false, |state| self.append_record(state, key, Some(bytes), level))
+    }
+
+    /// Records can't grow in place, so this writes the whole object again.
+    fn append(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+        let mut buff: Vec<u8> = self.read(key)?.unwrap_or_default();
+        buff.extend_from_slice(bytes);
+        self.write(key, &buff, level)
+    }
+
+    fn remove(&self, key: &str, level: SyncLevel) -> io::Result<()> {
+        self.with_state(false, |state| match state.keydir.contains_key(key) {
+            true => self.append_record(state, key, None, level),
+            false => Ok(()),
+        })
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        self.with_state(true, |state| Ok(state.keydir.keys().cloned().collect()))
+    }
+
+    fn lock(&self, exclusive: bool, mode: LockMode) -> io::Result<Option<LockGuard>> {
+        let guard: Option<lock::FileLock> = lock::acquire(&disk::sidecar(&self.dir, "lock"), exclusive, mode)?;
+        Ok(guard.map(|g| Box::new(g) as LockGuard))
+    }
+
+    /// For the empty key, the id and valid length of the active segment. Every write grows the
+    /// active segment or starts a new one, while a merge leaves it alone, as it doesn't change
+    /// what the storage holds.
+    fn fingerprint(&self, key: &str) -> io::Result<Option<u64>> {
+        let mut hasher: DefaultHasher = DefaultHasher::new();
+        let found: bool = match key {
+            "" => self.with_state(true, |state| {
+                state.segments.last_key_value().map(|(id, _)| id).hash(&mut hasher);
+                state.active_len.hash(&mut hasher);
+                Ok(!state.segments.is_empty())
+            })?,
+            key => self.with_state(false, |state| {
+                state.keydir.get(key).hash(&mut hasher);
+                Ok(state.keydir.contains_key(key))
+            })?,
+        };
+        Ok(found.then(|| hasher.finish()))
+    }
+
+    /// Writes the record of `key` as stored to a file next to its segment, then removes the key.
+    /// For the empty key, copies the first damaged segment aside and keeps its records before the damage.
+    fn move_aside(&self, key: &str, suffix: &str) -> io::Result<PathBuf> {
+        if key.is_empty() {
+            return self.move_damaged_aside(suffix);
+        }
+        let Some(record) = self.raw(key)? else {
+            return Err(io::Error::new(ErrorKind::NotFound, format!("no record under key ({key}) to move aside")));
+        };
+        let dest: PathBuf = disk::timestamped(&self.locate(key), suffix);
+        disk::write_atomic(&dest, &record, SyncLevel::All)?;
+        self.remove(key, SyncLevel::All)?;
+        Ok(dest)
+    }
+
+    fn copy_aside(&self, key: &str, suffix: &str) -> io::Result<Option<PathBuf>> {
+        let Some(value) = self.read(key)? else {
+            return Ok(None);
+        };
+        let dest: PathBuf = disk::timestamped(&self.locate(key), suffix);
+        disk::write_atomic(&dest, &value, SyncLevel::All)?;
+        Ok(Some(dest))
+    }
+
+    /// The segment holding `key`, or the directory for the empty or a missing key.
+    fn locate(&self, key: &str) -> PathBuf {
+        let segment: Option<u32> = self.with_state(false, |state| Ok(state.keydir.get(key).map(|e| e.segment))).ok().flatten();
+        match segment {
+            Some(id) => self.path(id, "data"),
+            None => self.dir.clone(),
+        }
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.with_state(false, |state| state.segments.values().try_for_each(File::sync_all))?;
+        if self.dir.exists() {
+            File::open(&self.dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Every segment counts towards the total, only the records the keydir points at are live.
+    fn usage(&self) -> io::Result<Usage> {
+        self.with_state(true, |state| {
+            let mut total: u64 = 0;
+            for file in state.segments.values() {
+                total += file.metadata()?.len();
+            }
+            Ok(Usage { total, live: state.live })
+        })
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        self.merge(false)
+    }
+}
+
+fn corrupted(path: PathBuf, reason: String) -> io::Error {
+    MemoError::Corrupted { path, reason }.into()
+}
+
+fn encode(key: &str, value: Option<&[u8]>) -> Vec<u8> {
+    let len: u32 = value.map_or(TOMBSTONE, |v| v.len() as u32);
+    let mut record: Vec<u8> = Vec::with_capacity(HEADER + key.len() + value.map_or(0, <[u8]>::len));
+    record.extend_from_slice(&[0; 4]);
+    record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(key.as_bytes());
+    record.extend_from_slice(value.unwrap_or_default());
+    let crc: u32 = crc32fast::hash(&record[4..]);
+    record[..4].copy_from_slice(&crc.to_le_bytes());
+    record
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// The record at `pos` of `bytes` as its key, value length and end: `Ok(None)` if it runs past the
+/// end, the reason if it is damaged.
+fn parse_record(bytes: &[u8], pos: usize) -> Result<Option<(&str, u32, usize)>, String> {
+    let Some(header) = bytes.get(pos..pos + HEADER) else {
+        return Ok(None);
+    };
+    let (key_len, len) = (u32_at(header, 4) as usize, u32_at(header, 8));
+    let value_len: usize = if len == TOMBSTONE { 0 } else { len as usize };
+    let Some(record) = bytes.get(pos..pos + HEADER + key_len + value_len) else {
+        return Ok(None);
+    };
+    // Keys are never empty, so this is a zeroed header, whose checksum would pass.
+    if key_len == 0 {
+        return Err(format!("record at offset {pos} has an empty key"));
+    }
+    if crc32fast::hash(&record[4..]) != u32_at(record, 0) {
+        return Err(format!("record at offset {pos} fails its checksum"));
+    }
+    let key: &str = std::str::from_utf8(&record[HEADER..HEADER + key_len])
+        .map_err(|_| format!("record at offset {pos} has a key that isn't utf-8"))?;
+    Ok(Some((key, len, pos + record.len())))
+}
+
+/// Replays the records of segment `id` into the keydir and returns where they end. The `active`
+/// segment may end in a record torn by a crash, cut short or damaged with no intact record after
+/// it, which is left out. Otherwise fails with where the damage starts and why.
+fn scan(bytes: &[u8], id: u32, active: bool, state: &mut State) -> Result<usize, (usize, String)> {
+    let mut pos: usize = 0;
+    while pos < bytes.len() {
+        let damage: String = match parse_record(bytes, pos) {
+            Ok(Some((key, len, end))) => {
+                let entry: Option<Entry> = (len != TOMBSTONE).then_some(Entry { segment: id, offset: pos as u64, len });
+                state.set(key, entry);
+                pos = end;
+                continue;
+            },
+            Ok(None) => format!("segment ends in the middle of the record at offset {pos}"),
+            Err(reason) => reason,
+        };
+        let intact_after: bool = (pos + 1..bytes.len()).any(|at| matches!(parse_record(bytes, at), Ok(Some(_))));
+        return match active && !intact_after {
+            true => Ok(pos),
+            false => Err((pos, damage)),
+        };
+    }
+    Ok(pos)
+}
+
+/// Reads the record of `key` from `file`, failing with [`MemoError::Corrupted`] if it doesn't check out.
+fn read_record(mut file: &File, key: &str, entry: &Entry, path: &std::path::Path) -> io::Result<Vec<u8>> {
+    let mut record: Vec<u8> = vec![0; entry.size(key)];
+    file.seek(SeekFrom::Start(entry.offset))?;
+    file.read_exact(&mut record)?;
+    if crc32fast::hash(&record[4..]) != u32_at(&record, 0) {
+        return Err(corrupted(path.to_path_buf(), format!("record of {key} at offset {} fails its checksum", entry.offset)));
+    }
+    Ok(record)
+}
+
+fn push_hint(hints: &mut Vec<u8>, key: &str, entry: &Entry) {
+    hints.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    hints.extend_from_slice(&entry.len.to_le_bytes());
+    hints.extend_from_slice(&entry.offset.to_le_bytes());
+    hints.extend_from_slice(key.as_bytes());
+}
+
+/// Reads a hint file of segment `id`, `None` if it is damaged and the segment has to be scanned instead.
+fn parse_hints(bytes: &[u8], id: u32) -> Option<Vec<(String, Entry)>> {
+    let (body, crc) = bytes.split_at_checked(bytes.len().checked_sub(4)?)?;
+    if crc32fast::hash(body) != u32_at(crc, 0) {
+        return None;
+    }
+    let mut hints: Vec<(String, Entry)> = Vec::new();
+    let mut pos: usize = 0;
+    while pos < body.len() {
+        let header: &[u8] = body.get(pos..pos + HINT_HEADER)?;
+        let key_len: usize = u32_at(header, 0) as usize;
+        let len: u32 = u32_at(header, 4);
+        let offset: u64 = u64::from_le_bytes(header[8..16].try_into().ok()?);
+        let key: &[u8] = body.get(pos + HINT_HEADER..pos + HINT_HEADER + key_len)?;
+        hints.push((String::from_utf8(key.to_vec()).ok()?, Entry { segment: id, offset, len }));
+        pos += HINT_HEADER + key_len;
+    }
+    Some(hints)
+}
@@ -0,0 +1,80 @@
+use std::{collections::{BTreeMap, HashMap}, sync::{Mutex, MutexGuard}};
+
+/// The documents a lazy database read most recently, at most `capacity` of them, see [`Config::cache`](crate::Config::cache).
+#[derive(Debug)]
+pub(crate) struct Cache<T> {
+    lru: Mutex<Lru<T>>,
+}
+
+#[derive(Debug, Clone)]
+struct Lru<T> {
+    capacity: usize,
+    /// Every cached document with the tick it was last used at.
+    docs: HashMap<String, (T, u64)>,
+    /// The ids by the tick they were last used at, oldest first.
+    order: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl<T: Clone> Cache<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { lru: Mutex::new(Lru { capacity, docs: HashMap::new(), order: BTreeMap::new(), tick: 0 }) }
+    }
+
+    fn lru(&self) -> MutexGuard<'_, Lru<T>> {
+        // Nothing can panic halfway through a change, so a poisoned lock still guards a consistent cache.
+        self.lru.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns a copy of the document cached under `id` and marks it as the most recently used.
+    pub(crate) fn get(&self, id: &str) -> Option<T> {
+        let mut lru = self.lru();
+        lru.tick += 1;
+        let tick: u64 = lru.tick;
+        let (doc, used) = lru.docs.get_mut(id)?;
+        let (doc, last) = (doc.clone(), std::mem::replace(used, tick));
+        lru.order.remove(&last);
+        lru.order.insert(tick, id.to_string());
+        Some(doc)
+    }
+
+    /// Caches `doc` under `id`, evicting the least recently used document if the cache is full.
+    pub(crate) fn insert(&self, id: &str, doc: T) {
+        let mut lru = self.lru();
+        if lru.capacity == 0 {
+            return;
+        }
+        Self::evict(&mut lru, id);
+        if lru.docs.len() >= lru.capacity {
+            if let Some((_, oldest)) = lru.order.pop_first() {
+                lru.docs.remove(&oldest);
+            }
+        }
+        lru.tick += 1;
+        let tick: u64 = lru.tick;
+        lru.docs.insert(id.to_string(), (doc, tick));
+        lru.order.insert(tick, id.to_string());
+    }
+
+    pub(crate) fn remove(&self, id: &str) {
+        Self::evict(&mut self.lru(), id);
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut lru = self.lru();
+        lru.docs.clear();
+        lru.order.clear();
+    }
+
+    fn evict(lru: &mut Lru<T>, id: &str) {
+        if let Some((_, used)) = lru.docs.remove(id) {
+            lru.order.remove(&used);
+        }
+    }
+}
+
+impl<T: Clone> Clone for Cache<T> {
+    fn clone(&self) -> Self {
+        Self { lru: Mutex::new(self.lru().clone()) }
+    }
+}
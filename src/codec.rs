@@ -0,0 +1,295 @@
+use std::{fmt::Debug, io};
+use serde_json::Value;
+
+#[doc = r#"Turns the stored map into the bytes of the database file and back.
+
+Codecs work on the `serde_json::Value` of the whole file, so they apply to every `MemoDoc` type
+unchanged. [`JsonPretty`] is the default; the binary codecs are behind the `msgpack`, `cbor`,
+`bincode` and `ron` cargo features. The journal always stores json lines.
+
+Going through `Value` means documents only keep what json can hold, whatever the codec: byte
+buffers are stored as arrays of numbers, integers must fit in an `i64` or `u64`, floats that aren't
+finite become `null`, and map keys become strings. The binary codecs save space and parsing time,
+not types.
+
+A file must be opened with the codec it was written with, otherwise it is reported as corrupt.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, JsonCompact, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+use std::sync::Arc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_codec.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let config = Config { codec: Arc::new(JsonCompact), ..Config::default() };
+    let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+    f.push(Task::default()).await.unwrap();
+    assert!(!std::fs::read_to_string(path).unwrap().contains('\n'));
+    assert_eq!(DataBase::<Task>::open_with(path, config).unwrap().docs.len(), 1);
+    # });
+}
+```"#]
+pub trait Codec: Debug + Send + Sync {
+    fn encode(&self, value: &Value) -> io::Result<Vec<u8>>;
+    fn decode(&self, bytes: &[u8]) -> io::Result<Value>;
+}
+
+#[cfg(any(feature = "msgpack", feature = "cbor", feature = "bincode", feature = "ron"))]
+fn invalid_data(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Indented json, readable and diffable. This is the default codec.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonPretty;
+
+impl Codec for JsonPretty {
+    fn encode(&self, value: &Value) -> io::Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(value)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<Value> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Json without any whitespace.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCompact;
+
+impl Codec for JsonCompact {
+    fn encode(&self, value: &Value) -> io::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<Value> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// [MessagePack](https://msgpack.org), through `rmp-serde`.
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use memorable::{Config, DataBase, MemoDoc, MessagePack};
+/// use memorable_macro_derive::MemoDoc;
+/// use std::sync::Arc;
+///
+/// #[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+/// struct Task {
+///     uuid: String,
+///     tags: Vec<String>,
+///     weight: f64
+/// }
+///
+/// # let path = std::env::temp_dir().join("memorable_doc_codec.msgpack");
+/// # let path = path.to_str().unwrap();
+/// # let _ = std::fs::remove_file(path);
+/// # pollster::block_on(async {
+/// let config = Config { codec: Arc::new(MessagePack), ..Config::default() };
+/// let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+/// let task = Task { uuid: "a".into(), tags: vec!["x".into()], weight: -1.5 };
+/// f.push(task.clone()).await.unwrap();
+/// assert_eq!(DataBase::<Task>::open_with(path, config).unwrap().docs["a"], task);
+/// # });
+/// ```
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode(&self, value: &Value) -> io::Result<Vec<u8>> {
+        rmp_serde::to_vec(value).map_err(invalid_data)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<Value> {
+        rmp_serde::from_slice(bytes).map_err(invalid_data)
+    }
+}
+
+/// [CBOR](https://cbor.io), through `ciborium`.
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use memorable::{Config, DataBase, MemoDoc, Cbor};
+/// use memorable_macro_derive::MemoDoc;
+/// use std::sync::Arc;
+///
+/// #[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+/// struct Task {
+///     uuid: String,
+///     tags: Vec<String>,
+///     weight: f64
+/// }
+///
+/// # let path = std::env::temp_dir().join("memorable_doc_codec.cbor");
+/// # let path = path.to_str().unwrap();
+/// # let _ = std::fs::remove_file(path);
+/// # pollster::block_on(async {
+/// let config = Config { codec: Arc::new(Cbor), ..Config::default() };
+/// let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+/// let task = Task { uuid: "a".into(), tags: vec!["x".into()], weight: -1.5 };
+/// f.push(task.clone()).await.unwrap();
+/// assert_eq!(DataBase::<Task>::open_with(path, config).unwrap().docs["a"], task);
+/// # });
+/// ```
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn encode(&self, value: &Value) -> io::Result<Vec<u8>> {
+        let mut buff: Vec<u8> = Vec::new();
+        ciborium::into_writer(value, &mut buff).map_err(invalid_data)?;
+        Ok(buff)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<Value> {
+        ciborium::from_reader(bytes).map_err(invalid_data)
+    }
+}
+
+/// [bincode](https://docs.rs/bincode/1), the most compact and fastest codec, but not self-describing.
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use memorable::{Config, DataBase, MemoDoc, Bincode};
+/// use memorable_macro_derive::MemoDoc;
+/// use std::sync::Arc;
+///
+/// #[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+/// struct Task {
+///     uuid: String,
+///     tags: Vec<String>,
+///     weight: f64
+/// }
+///
+/// # let path = std::env::temp_dir().join("memorable_doc_codec.bincode");
+/// # let path = path.to_str().unwrap();
+/// # let _ = std::fs::remove_file(path);
+/// # pollster::block_on(async {
+/// let config = Config { codec: Arc::new(Bincode), ..Config::default() };
+/// let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+/// let task = Task { uuid: "a".into(), tags: vec!["x".into()], weight: -1.5 };
+/// f.push(task.clone()).await.unwrap();
+/// assert_eq!(DataBase::<Task>::open_with(path, config).unwrap().docs["a"], task);
+/// # });
+/// ```
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+/// bincode can't de-serialize a `Value` on its own as it needs to know the type up front, so
+/// values are stored as this explicitly tagged tree instead.
+#[cfg(feature = "bincode")]
+#[derive(serde::Serialize, serde::Deserialize)]
+enum Tree {
+    Null,
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    String(String),
+    Array(Vec<Tree>),
+    Object(Vec<(String, Tree)>),
+}
+
+#[cfg(feature = "bincode")]
+impl From<&Value> for Tree {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => Tree::Null,
+            Value::Bool(b) => Tree::Bool(*b),
+            Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+                (Some(u), _) => Tree::U64(u),
+                (None, Some(i)) => Tree::I64(i),
+                (None, None) => Tree::F64(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(s) => Tree::String(s.clone()),
+            Value::Array(a) => Tree::Array(a.iter().map(Tree::from).collect()),
+            Value::Object(o) => Tree::Object(o.iter().map(|(k, v)| (k.clone(), Tree::from(v))).collect()),
+        }
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl From<Tree> for Value {
+    fn from(tree: Tree) -> Self {
+        match tree {
+            Tree::Null => Value::Null,
+            Tree::Bool(b) => Value::Bool(b),
+            Tree::U64(u) => Value::from(u),
+            Tree::I64(i) => Value::from(i),
+            Tree::F64(f) => Value::from(f),
+            Tree::String(s) => Value::String(s),
+            Tree::Array(a) => Value::Array(a.into_iter().map(Value::from).collect()),
+            Tree::Object(o) => Value::Object(o.into_iter().map(|(k, v)| (k, Value::from(v))).collect()),
+        }
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode(&self, value: &Value) -> io::Result<Vec<u8>> {
+        bincode::serialize(&Tree::from(value)).map_err(invalid_data)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<Value> {
+        bincode::deserialize::<Tree>(bytes).map(Value::from).map_err(invalid_data)
+    }
+}
+
+/// [RON](https://github.com/ron-rs/ron), a readable alternative to json.
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use memorable::{Config, DataBase, MemoDoc, Ron};
+/// use memorable_macro_derive::MemoDoc;
+/// use std::sync::Arc;
+///
+/// #[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+/// struct Task {
+///     uuid: String,
+///     tags: Vec<String>,
+///     weight: f64
+/// }
+///
+/// # let path = std::env::temp_dir().join("memorable_doc_codec.ron");
+/// # let path = path.to_str().unwrap();
+/// # let _ = std::fs::remove_file(path);
+/// # pollster::block_on(async {
+/// let config = Config { codec: Arc::new(Ron), ..Config::default() };
+/// let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+/// let task = Task { uuid: "a".into(), tags: vec!["x".into()], weight: -1.5 };
+/// f.push(task.clone()).await.unwrap();
+/// assert_eq!(DataBase::<Task>::open_with(path, config).unwrap().docs["a"], task);
+/// # });
+/// ```
+#[cfg(feature = "ron")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ron;
+
+#[cfg(feature = "ron")]
+impl Codec for Ron {
+    fn encode(&self, value: &Value) -> io::Result<Vec<u8>> {
+        ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())
+            .map(String::into_bytes)
+            .map_err(invalid_data)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> io::Result<Value> {
+        ron::de::from_bytes(bytes).map_err(invalid_data)
+    }
+}
@@ -0,0 +1,128 @@
+use std::io;
+#[cfg(feature = "gzip")]
+use std::io::{Read, Write};
+
+/// First bytes of a zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+/// First bytes of a gzip member.
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+#[doc = r#"How the encoded database file is compressed before it is written, see [`Config::compression`](crate::Config::compression).
+
+The compressed formats are behind the `zstd` and `gzip` cargo features. Reading doesn't depend on
+this setting: `open` recognises compressed files by their magic bytes, so switching it on or off
+keeps existing files loadable and converts them on the next write."#]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Compression {
+    #[default]
+    None,
+    /// [zstd](https://facebook.github.io/zstd) at the given level, 1 (fastest) to 22 (smallest).
+    /// Level 3 is a good default.
+    ///
+    /// ```
+    /// use serde::{Deserialize, Serialize};
+    /// use memorable::{Compression, Config, DataBase, MemoDoc};
+    /// use memorable_macro_derive::MemoDoc;
+    ///
+    /// #[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+    /// struct Task {
+    ///     uuid: String
+    /// }
+    ///
+    /// # let path = std::env::temp_dir().join("memorable_doc_compression.zst");
+    /// # let path = path.to_str().unwrap();
+    /// # let _ = std::fs::remove_file(path);
+    /// # pollster::block_on(async {
+    /// let config = Config { compression: Compression::Zstd(3), ..Config::default() };
+    /// let mut f: DataBase<Task> = DataBase::open_with(path, config).unwrap();
+    /// f.push(Task::default()).await.unwrap();
+    /// assert_eq!(std::fs::read(path).unwrap()[..4], [0x28, 0xB5, 0x2F, 0xFD]);
+    ///
+    /// // The default config still reads the file, and writes it back uncompressed.
+    /// let mut f: DataBase<Task> = DataBase::open(path).unwrap();
+    /// assert_eq!(f.docs.len(), 1);
+    /// f.push(Task::default()).await.unwrap();
+    /// assert_eq!(std::fs::read(path).unwrap()[0], b'{');
+    /// # });
+    /// ```
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+    /// gzip at the given level, 0 (store only) to 9 (smallest). Level 6 is a good default.
+    ///
+    /// ```
+    /// use serde::{Deserialize, Serialize};
+    /// use memorable::{Compression, Config, DataBase, MemoDoc};
+    /// use memorable_macro_derive::MemoDoc;
+    ///
+    /// #[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+    /// struct Task {
+    ///     uuid: String
+    /// }
+    ///
+    /// # let path = std::env::temp_dir().join("memorable_doc_compression.gz");
+    /// # let path = path.to_str().unwrap();
+    /// # let _ = std::fs::remove_file(path);
+    /// # pollster::block_on(async {
+    /// let mut f: DataBase<Task> = DataBase::open(path).unwrap();
+    /// f.push(Task::default()).await.unwrap();
+    ///
+    /// let config = Config { compression: Compression::Gzip(6), ..Config::default() };
+    /// let mut f: DataBase<Task> = DataBase::open_with(path, config).unwrap();
+    /// f.push(Task::default()).await.unwrap();
+    /// assert_eq!(std::fs::read(path).unwrap()[..2], [0x1F, 0x8B]);
+    /// assert_eq!(DataBase::<Task>::open(path).unwrap().docs.len(), 2);
+    /// # });
+    /// ```
+    #[cfg(feature = "gzip")]
+    Gzip(u32),
+}
+
+/// Compresses the encoded file `bytes` as configured.
+pub(crate) fn compress(bytes: Vec<u8>, compression: Compression) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(bytes),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd(level) => zstd::stream::encode_all(bytes.as_slice(), level),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip(level) => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+            encoder.write_all(&bytes)?;
+            encoder.finish()
+        },
+    }
+}
+
+/// Decompresses a file read from disk according to its magic bytes, passing plain files through.
+///
+/// Fails with [`ErrorKind::Unsupported`](io::ErrorKind::Unsupported) if the file is compressed with
+/// a format whose cargo feature isn't enabled, and with `InvalidData` if it is damaged.
+pub(crate) fn decompress(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+    if bytes.starts_with(&ZSTD_MAGIC) {
+        #[cfg(feature = "zstd")]
+        return zstd::stream::decode_all(bytes.as_slice()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        #[cfg(not(feature = "zstd"))]
+        return Err(missing_feature("zstd"));
+    }
+    if bytes.starts_with(&GZIP_MAGIC) {
+        #[cfg(feature = "gzip")]
+        {
+            let mut buff: Vec<u8> = Vec::new();
+            flate2::read::GzDecoder::new(bytes.as_slice())
+                .read_to_end(&mut buff)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            return Ok(buff);
+        }
+        #[cfg(not(feature = "gzip"))]
+        return Err(missing_feature("gzip"));
+    }
+    Ok(bytes)
+}
+
+#[cfg(not(all(feature = "zstd", feature = "gzip")))]
+fn missing_feature(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("file is {name} compressed but memorable was built without the `{name}` feature"),
+    )
+}
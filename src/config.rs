@@ -0,0 +1,433 @@
+use std::sync::Arc;
+
+use crate::{Codec, Compression, JsonPretty, Migrations, Usage};
+#[cfg(feature = "encryption")]
+use crate::Encryption;
+
+#[doc = r#"How a [`DataBase`](crate::DataBase) persists its mutations.
+
+- `Snapshot` rewrites the whole json file on every `push` and `del`.
+- `Journal` appends every `push` and `del` as a single record to `<file_path>.journal`.
+  On `open` the journal is replayed over the snapshot file, and once it grows past
+  [`Config::journal_limit`] bytes it is folded back into the snapshot.
+- `Lines` stores the database itself as json lines, one `{"op":"put","id":..,"doc":..}` record per
+  document. `push` appends a line, `del` appends a `{"op":"del","id":..}` tombstone, and
+  [`DataBase::compact`](crate::DataBase::compact) rewrites the file without tombstones and overwritten lines.
+  A write of several documents at once, like [`DataBase::push_many`](crate::DataBase::push_many) or a
+  transaction, is preceded by a `{"op":"begin","count":..}` line that isn't a document: its records
+  only count if all `count` of them follow, so that a crash halfway through leaves none of them.
+  Tools reading the file should skip these lines, and drop a batch that is cut short.
+  The file has no header, so it ignores [`Config::envelope`], [`Config::codec`] and [`Config::compression`],
+  and a [`Config::schema_version`] above 0 is refused.
+- `Directory` treats `file_path` as a directory holding every document as its own `<id>.json` file,
+  with ids escaped into valid file names. `push` writes one file and `del` removes one, each atomically.
+  Like `Lines` it ignores the envelope, codec and compression settings and refuses a schema version.
+  See [`Config::lazy`] to open large collections without reading them.
+- `Bitcask` treats `file_path` as a directory of append-only segment files, see
+  [`BitcaskStorage`](crate::BitcaskStorage). `push` and `del` append one binary record, reads of a
+  lazy database seek straight to it, and old segments are merged in the background or by
+  [`DataBase::compact`](crate::DataBase::compact). It ignores and refuses the same settings as `Directory`.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, MemoDoc, StorageMode};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_lines.jsonl");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let config = Config { mode: StorageMode::Lines, ..Config::default() };
+    let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+    f.push(Task { uuid: "a".into() }).await.unwrap();
+    f.push(Task { uuid: "b".into() }).await.unwrap();
+    f.del("a").await.unwrap();
+    assert_eq!(std::fs::read_to_string(path).unwrap().lines().last(), Some("{\"op\":\"del\",\"id\":\"a\"}"));
+
+    f.compact().unwrap();
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "{\"op\":\"put\",\"id\":\"b\",\"doc\":{\"uuid\":\"b\"}}\n");
+    assert_eq!(DataBase::<Task>::open_with(path, config).unwrap().docs.len(), 1);
+
+    # let dir = std::env::temp_dir().join("memorable_doc_directory");
+    # let dir = dir.to_str().unwrap();
+    # let _ = std::fs::remove_dir_all(dir);
+    let config = Config { mode: StorageMode::Directory, ..Config::default() };
+    let mut f: DataBase<Task> = DataBase::open_with(dir, config.clone()).unwrap();
+    f.push(Task { uuid: "notes/Today".into() }).await.unwrap();
+    assert!(std::path::Path::new(dir).join("notes%2FToday.json").exists());
+    assert!(DataBase::<Task>::open_with(dir, config).unwrap().docs.contains_key("notes/Today"));
+    # });
+}
+```"#]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageMode {
+    #[default]
+    Snapshot,
+    Journal,
+    Lines,
+    Directory,
+    Bitcask,
+}
+
+#[doc = r#"When writes to the database file (and its journal) are forced to the disk.
+
+- `None` never syncs and leaves flushing to the OS; a power loss can lose recent writes or,
+  because the rename may reach the disk before the data, leave an empty file.
+- `Flush` flushes the written file's data with `sync_data` but not its metadata or directory entry.
+- `Fsync` fully fsyncs the written file and its directory on every write. This is the default.
+- `FsyncInterval(d)` behaves like `Fsync` on the first write after `d` has elapsed since the last
+  full sync, and like `None` otherwise. Call [`DataBase::sync`](crate::DataBase::sync) to force a
+  sync before shutting down.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, Durability, MemoDoc, StorageMode};
+use memorable_macro_derive::MemoDoc;
+use std::time::Duration;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let dir = std::env::temp_dir().join("memorable_doc_durability");
+    # let _ = std::fs::remove_dir_all(&dir);
+    # std::fs::create_dir_all(&dir).unwrap();
+    # pollster::block_on(async {
+    // (level, whether a push right after `open` is fully synced)
+    let levels = [
+        (Durability::None, false),
+        (Durability::Flush, false),
+        (Durability::Fsync, true),
+        // Creating the file in `open` was the synced write of this interval.
+        (Durability::FsyncInterval(Duration::from_secs(3600)), false),
+    ];
+    for (i, (durability, syncs)) in levels.into_iter().enumerate() {
+        for mode in [StorageMode::Snapshot, StorageMode::Journal] {
+            let path = dir.join(format!("{i}-{mode:?}.json"));
+            let path = path.to_str().unwrap();
+            let config = Config { mode, durability, ..Config::default() };
+
+            let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+            let opened = f.last_sync();
+            f.push(Task::default()).await.unwrap();
+            assert_eq!(f.last_sync() != opened, syncs);
+            f.sync().unwrap();
+            assert!(f.last_sync().is_some());
+
+            let f: DataBase<Task> = DataBase::open_with(path, config).unwrap();
+            assert!(!f.docs.is_empty());
+        }
+    }
+    # });
+}
+```"#]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    None,
+    Flush,
+    #[default]
+    Fsync,
+    FsyncInterval(std::time::Duration),
+}
+
+#[doc = r#"What [`DataBase::open_with`](crate::DataBase::open_with) does when the database file or its
+journal exists but can't be parsed.
+
+- `Error` fails with [`MemoError::Corrupted`](crate::MemoError::Corrupted) and leaves the file untouched. This is the default.
+- `Backup` moves the broken file to `<file>.<unix millis>.corrupt` and starts from what could
+  still be read. The backups are listed by [`DataBase::recovered`](crate::DataBase::recovered)."#]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CorruptionPolicy {
+    #[default]
+    Error,
+    Backup,
+}
+
+#[doc = r#"How a [`DataBase`](crate::DataBase) waits for the advisory lock on `<file_path>.lock`.
+
+Reading the file in `open` takes a shared lock, every write (`push`, `del`, compaction) takes an
+exclusive one, so processes sharing a database serialize their writes instead of losing them.
+The lock is only held for the duration of each call.
+
+- `None` doesn't lock at all.
+- `Blocking` waits as long as it takes. This is the default.
+- `Try` fails right away with [`MemoError::Locked`](crate::MemoError::Locked) if the lock is taken.
+- `Timeout(d)` retries for up to `d` before failing with [`MemoError::Locked`](crate::MemoError::Locked).
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, LockMode, MemoDoc, MemoError};
+use memorable_macro_derive::MemoDoc;
+use std::{fs::File, thread, time::Duration};
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_lock_mode.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let config = |locking| Config { locking, ..Config::default() };
+
+    // Stands in for another process in the middle of a write.
+    let other = File::create(format!("{path}.lock")).unwrap();
+    other.lock().unwrap();
+
+    for locking in [LockMode::Try, LockMode::Timeout(Duration::from_millis(50))] {
+        let err = DataBase::<Task>::open_with(path, config(locking)).unwrap_err();
+        assert!(matches!(MemoError::from_io(&err), Some(MemoError::Locked { .. })));
+    }
+
+    let releaser = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        other.unlock().unwrap();
+    });
+    let mut f: DataBase<Task> = DataBase::open_with(path, config(LockMode::Blocking)).unwrap();
+    f.push(Task::default()).await.unwrap();
+    releaser.join().unwrap();
+    # });
+}
+```"#]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockMode {
+    None,
+    #[default]
+    Blocking,
+    Try,
+    Timeout(std::time::Duration),
+}
+
+#[doc = r#"What a write does when the database file (or its journal) was changed by someone else since this
+[`DataBase`](crate::DataBase) last read or wrote it.
+
+- `Reload` reloads `docs` from disk and then retries the write against the fresh data. This is the default.
+- `Error` fails with [`MemoError::Conflict`](crate::MemoError::Conflict) and leaves both `docs` and the file untouched.
+- `Overwrite` goes ahead with the in-memory `docs`, discarding the other writer's changes.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, ConflictPolicy, DataBase, MemoDoc, MemoError};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_conflict_policy.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let config = |on_conflict| Config { on_conflict, ..Config::default() };
+    let mut cli: DataBase<Task> = DataBase::open(path).unwrap();
+    let mut daemon: DataBase<Task> = DataBase::open_with(path, config(ConflictPolicy::Error)).unwrap();
+
+    cli.push(Task::default()).await.unwrap();
+    let err = daemon.push(Task::default()).await.unwrap_err();
+    assert!(matches!(MemoError::from_io(&err), Some(MemoError::Conflict { .. })));
+
+    let mut daemon: DataBase<Task> = DataBase::open_with(path, config(ConflictPolicy::Reload)).unwrap();
+    cli.push(Task::default()).await.unwrap();
+    daemon.push(Task::default()).await.unwrap();
+    assert_eq!(daemon.docs.len(), 3);
+
+    cli.push(Task::default()).await.unwrap();
+    let mut daemon = DataBase::<Task>::open_with(path, config(ConflictPolicy::Overwrite)).unwrap();
+    cli.push(Task::default()).await.unwrap();
+    daemon.push(Task::default()).await.unwrap();
+    // The cli's fifth task was overwritten by the daemon's stale view.
+    assert_eq!(DataBase::<Task>::open(path).unwrap().docs.len(), 5);
+    # });
+}
+```"#]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    Reload,
+    Error,
+    Overwrite,
+}
+
+#[doc = r#"When a [`StorageMode::Lines`] file or [`StorageMode::Bitcask`] directory is compacted without
+calling [`DataBase::compact`](crate::DataBase::compact), checked after every `push` and `del`.
+
+- `Never` leaves it to `compact`, and to the background merges of [`BitcaskStorage`](crate::BitcaskStorage). This is the default.
+- `Garbage { ratio, min_size }` compacts once overwritten and removed records take up more than
+  `ratio` (0.0 to 1.0) of the storage, if it is at least `min_size` bytes.
+- `Size(bytes)` compacts once the storage is larger than `bytes` and holds any garbage at all.
+  Pick it well above the size of the live documents, or nearly every write will compact.
+
+[`StorageMode::Journal`] folds its journal by [`Config::journal_limit`] instead, the other modes
+keep no garbage.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{AutoCompact, Config, DataBase, MemoDoc, StorageMode};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_auto_compact.jsonl");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let auto_compact = AutoCompact::Garbage { ratio: 0.5, min_size: 0 };
+    let config = Config { mode: StorageMode::Lines, auto_compact, ..Config::default() };
+    let mut f: DataBase<Task> = DataBase::open_with(path, config).unwrap();
+    f.push(Task { uuid: "a".into() }).await.unwrap();
+    f.push(Task { uuid: "b".into() }).await.unwrap();
+    f.del("a").await.unwrap();
+    // The put and tombstone of `a` outweighed `b`, so the file was rewritten.
+    assert_eq!(std::fs::read_to_string(path).unwrap().lines().count(), 1);
+    # });
+}
+```"#]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AutoCompact {
+    #[default]
+    Never,
+    Garbage { ratio: f64, min_size: u64 },
+    Size(u64),
+}
+
+impl AutoCompact {
+    /// Whether a storage using `usage` is due for compaction.
+    pub(crate) fn due(&self, usage: Usage) -> bool {
+        match *self {
+            AutoCompact::Never => false,
+            AutoCompact::Garbage { ratio, min_size } => usage.total >= min_size && usage.garbage_ratio() > ratio,
+            AutoCompact::Size(bytes) => usage.total > bytes && usage.live < usage.total,
+        }
+    }
+}
+
+#[doc = r#"Settings used by [`DataBase::open_with`](crate::DataBase::open_with).
+
+# Examples
+```
+use memorable::{Config, StorageMode};
+
+let config = Config {
+    mode: StorageMode::Journal,
+    ..Config::default()
+};
+```"#]
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub mode: StorageMode,
+    pub durability: Durability,
+    pub on_corruption: CorruptionPolicy,
+    pub locking: LockMode,
+    pub on_conflict: ConflictPolicy,
+    /// Wraps the stored map in an envelope with a [`Header`](crate::Header), document count and checksum,
+    /// so `open` and [`DataBase::verify`](crate::DataBase::verify) can tell a damaged file from a
+    /// valid one. On by default; turning it off writes the plain map of older versions, without
+    /// format version. Plain and enveloped files are both read regardless of this setting.
+    pub envelope: bool,
+    /// Version of the document type `T`, recorded in the envelope's header. Any version above 0
+    /// writes the envelope even without [`Config::envelope`], so the version isn't lost.
+    pub schema_version: u32,
+    /// Upgrades applied by `open` to files of an older `schema_version`.
+    pub migrations: Migrations,
+    /// Encodes the database file, [`JsonPretty`] by default.
+    pub codec: Arc<dyn Codec>,
+    /// Compresses the encoded database file, and the snapshots taken of it. Compressed and plain
+    /// files are both read regardless of this setting; the journal is never compressed.
+    pub compression: Compression,
+    /// Encrypts the database file, and the snapshots taken of it. Only works with
+    /// [`StorageMode::Snapshot`], `open` refuses it with every other mode.
+    #[cfg(feature = "encryption")]
+    pub encryption: Option<Encryption>,
+    /// Size in bytes the journal may reach before it is compacted into the snapshot.
+    pub journal_limit: u64,
+    /// When lines files and bitcask directories are compacted automatically.
+    pub auto_compact: AutoCompact,
+    /// With [`StorageMode::Directory`], [`StorageMode::Bitcask`] or [`StorageMode::Lines`], `open`
+    /// doesn't de-serialize any document and `docs` stays empty, written documents included:
+    /// [`DataBase::get`](crate::DataBase::get) and [`DataBase::del`](crate::DataBase::del) read them
+    /// from storage, and [`DataBase::ids`](crate::DataBase::ids) lists them all. A lines
+    /// file is scanned once to index where each document's line is, so only the ids and offsets
+    /// are kept in memory, and compacting it copies the unread lines to the new file one at a
+    /// time. [`StorageMode::Snapshot`] and [`StorageMode::Journal`] have to read the
+    /// whole file, so `open` refuses them with kind `InvalidInput`.
+    ///
+    /// ```
+    /// use serde::{Deserialize, Serialize};
+    /// use memorable::{Config, DataBase, MemoDoc, StorageMode};
+    /// use memorable_macro_derive::MemoDoc;
+    ///
+    /// #[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+    /// struct Task {
+    ///     uuid: String,
+    ///     done: bool
+    /// }
+    ///
+    /// # let path = std::env::temp_dir().join("memorable_doc_lazy_lines.jsonl");
+    /// # let path = path.to_str().unwrap();
+    /// # let _ = std::fs::remove_file(path);
+    /// # pollster::block_on(async {
+    /// let config = Config { mode: StorageMode::Lines, ..Config::default() };
+    /// let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+    /// for i in 0..100 {
+    ///     f.push(Task { uuid: format!("{i:03}"), done: i % 2 == 0 }).await.unwrap();
+    /// }
+    ///
+    /// let config = Config { lazy: true, cache: 10, ..config };
+    /// let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+    /// assert!(f.docs.is_empty());
+    /// assert_eq!(f.ids().unwrap().len(), 100);
+    /// assert!(f.get("042").await.unwrap().done);
+    /// f.del("042").await.unwrap();
+    /// f.compact().unwrap();
+    /// assert_eq!(DataBase::<Task>::open_with(path, config).unwrap().ids().unwrap().len(), 99);
+    /// # });
+    /// ```
+    pub lazy: bool,
+    /// How many documents a lazy database keeps after [`DataBase::get`](crate::DataBase::get) read
+    /// them or a write stored them, dropping the least recently used first. 0, the default, reads every time.
+    pub cache: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: StorageMode::Snapshot,
+            durability: Durability::Fsync,
+            on_corruption: CorruptionPolicy::Error,
+            locking: LockMode::Blocking,
+            on_conflict: ConflictPolicy::Reload,
+            envelope: true,
+            schema_version: 0,
+            migrations: Migrations::new(),
+            codec: Arc::new(JsonPretty),
+            compression: Compression::None,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+            journal_limit: 1024 * 1024,
+            auto_compact: AutoCompact::Never,
+            lazy: false,
+            cache: 0,
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use std::{io, path::Path};
+#[cfg(feature = "encryption")]
+use std::{collections::HashMap, fmt, sync::Arc};
+#[cfg(feature = "encryption")]
+use aes_gcm::{aead::{rand_core::RngCore, Aead, KeyInit, OsRng, Payload}, Aes256Gcm};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::ChaCha20Poly1305;
+
+use crate::Config;
+#[cfg(feature = "encryption")]
+use crate::MemoError;
+
+/// First bytes of an encrypted file, the last one being the version of the layout below.
+///
+/// An encrypted file is laid out as `magic | cipher (1) | key id length (1) | key id | check nonce (12) |
+/// check tag (16) | nonce (12) | ciphertext and tag`. The check tag authenticates nothing but the
+/// header, so a wrong key is told apart from a damaged body.
+const MAGIC: [u8; 8] = *b"MEMOENC\x01";
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 12;
+#[cfg(feature = "encryption")]
+const TAG_LEN: usize = 16;
+
+#[doc = r#"The authenticated cipher a database file is encrypted with, see [`Encryption`]."#]
+#[cfg(feature = "encryption")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Cipher {
+    #[default]
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+#[cfg(feature = "encryption")]
+impl Cipher {
+    fn id(self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => 1,
+            Cipher::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Cipher> {
+        match id {
+            1 => Some(Cipher::Aes256Gcm),
+            2 => Some(Cipher::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    fn seal(self, key: &[u8; 32], nonce: &[u8], aad: &[u8], msg: &[u8]) -> io::Result<Vec<u8>> {
+        let payload: Payload = Payload { msg, aad };
+        match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).encrypt(nonce.into(), payload),
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into()).encrypt(nonce.into(), payload),
+        }
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "encryption failed"))
+    }
+
+    /// Returns `None` if the tag doesn't authenticate the message under `key`.
+    fn open(self, key: &[u8; 32], nonce: &[u8], aad: &[u8], msg: &[u8]) -> Option<Vec<u8>> {
+        let payload: Payload = Payload { msg, aad };
+        match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).decrypt(nonce.into(), payload),
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into()).decrypt(nonce.into(), payload),
+        }
+        .ok()
+    }
+}
+
+#[doc = r#"Supplies the 256 bit keys used by [`Encryption`].
+
+Every key has an id, which is stored in the file's header so the file can still be read after
+the current key was rotated. [`KeyRing`] covers keys held in memory; implement this trait to fetch
+them from a KMS, keychain or environment instead."#]
+#[cfg(feature = "encryption")]
+pub trait KeyProvider: fmt::Debug + Send + Sync {
+    /// The id and key new writes are encrypted with.
+    fn current(&self) -> io::Result<(String, [u8; 32])>;
+    /// The key with the given `id`, `None` if the provider doesn't have it.
+    fn get(&self, id: &str) -> io::Result<Option<[u8; 32]>>;
+}
+
+#[doc = r#"A [`KeyProvider`] holding the current key and any number of retired ones.
+
+Files encrypted with a retired key are still read, and are re-encrypted with the current key on
+the next write, so rotating a key is a matter of moving the old one to [`KeyRing::retired`].
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, Encryption, KeyRing, MemoDoc, MemoError};
+use memorable_macro_derive::MemoDoc;
+use std::sync::Arc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_key_ring.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let config = |keys: KeyRing| Config { encryption: Some(Encryption::new(Arc::new(keys))), ..Config::default() };
+    let mut f: DataBase<Task> = DataBase::open_with(path, config(KeyRing::new("2025", [1; 32]))).unwrap();
+    f.push(Task::default()).await.unwrap();
+
+    // Rotate: the file is read with the retired key and written with the new one.
+    let rotated = KeyRing::new("2026", [2; 32]).retired("2025", [1; 32]);
+    let mut f: DataBase<Task> = DataBase::open_with(path, config(rotated)).unwrap();
+    f.push(Task::default()).await.unwrap();
+
+    let err = DataBase::<Task>::open_with(path, config(KeyRing::new("2025", [1; 32]))).unwrap_err();
+    assert!(matches!(MemoError::from_io(&err), Some(MemoError::WrongKey { key, .. }) if key == "2026"));
+    assert_eq!(DataBase::<Task>::open_with(path, config(KeyRing::new("2026", [2; 32]))).unwrap().docs.len(), 2);
+    # });
+}
+```"#]
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+pub struct KeyRing {
+    current: String,
+    keys: HashMap<String, [u8; 32]>,
+}
+
+#[cfg(feature = "encryption")]
+impl KeyRing {
+    /// A key ring encrypting with `key`, stored under `id`.
+    pub fn new(id: impl Into<String>, key: [u8; 32]) -> Self {
+        let current: String = id.into();
+        Self { keys: HashMap::from([(current.clone(), key)]), current }
+    }
+
+    /// Adds a key that is only used to read files written before it was rotated out.
+    pub fn retired(mut self, id: impl Into<String>, key: [u8; 32]) -> Self {
+        self.keys.entry(id.into()).or_insert(key);
+        self
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ids: Vec<&String> = self.keys.keys().collect();
+        ids.sort();
+        f.debug_struct("KeyRing").field("current", &self.current).field("ids", &ids).finish()
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl KeyProvider for KeyRing {
+    fn current(&self) -> io::Result<(String, [u8; 32])> {
+        Ok((self.current.clone(), self.keys[&self.current]))
+    }
+
+    fn get(&self, id: &str) -> io::Result<Option<[u8; 32]>> {
+        Ok(self.keys.get(id).copied())
+    }
+}
+
+#[doc = r#"Authenticated encryption of the database file at rest, see [`Config::encryption`](crate::Config::encryption).
+
+The whole file, after the codec and any [`Compression`](crate::Compression), is sealed with
+`cipher` under the current key of `keys`. Snapshots and backups are encrypted the same way.
+Opening a file whose key the provider doesn't have, or that was written with a different key
+under the same id, fails with [`MemoError::WrongKey`](crate::MemoError::WrongKey) and leaves the
+file alone; a file whose contents fail to authenticate is [corrupt](crate::MemoError::Corrupted).
+
+Only the single file of [`StorageMode::Snapshot`](crate::StorageMode::Snapshot) is encrypted, so
+every other mode is refused: the journal and lines files are appended to in plain json, and the
+per-document modes store every document on its own.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Cipher, Config, DataBase, Encryption, KeyRing, MemoDoc, MemoError};
+use memorable_macro_derive::MemoDoc;
+use std::sync::Arc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Patient {
+    uuid: String,
+    name: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_encryption.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let encryption = Encryption { cipher: Cipher::ChaCha20Poly1305, ..Encryption::new(Arc::new(KeyRing::new("k1", [7; 32]))) };
+    let config = Config { encryption: Some(encryption), ..Config::default() };
+    let mut f: DataBase<Patient> = DataBase::open_with(path, config.clone()).unwrap();
+    f.push(Patient { uuid: "p".into(), name: "Ada Lovelace".into() }).await.unwrap();
+
+    assert!(!String::from_utf8_lossy(&std::fs::read(path).unwrap()).contains("Lovelace"));
+    assert_eq!(DataBase::<Patient>::open_with(path, config).unwrap().docs["p"].name, "Ada Lovelace");
+    let err = DataBase::<Patient>::open(path).unwrap_err();
+    assert!(matches!(MemoError::from_io(&err), Some(MemoError::WrongKey { .. })));
+    # });
+}
+```"#]
+#[cfg(feature = "encryption")]
+#[derive(Debug, Clone)]
+pub struct Encryption {
+    pub cipher: Cipher,
+    pub keys: Arc<dyn KeyProvider>,
+}
+
+#[cfg(feature = "encryption")]
+impl Encryption {
+    /// Encryption with [`Cipher::Aes256Gcm`] and the keys of `keys`.
+    pub fn new(keys: Arc<dyn KeyProvider>) -> Self {
+        Self { cipher: Cipher::default(), keys }
+    }
+}
+
+/// Encrypts the file `bytes` if [`Config::encryption`] is set.
+#[cfg(feature = "encryption")]
+pub(crate) fn encrypt(bytes: Vec<u8>, config: &Config) -> io::Result<Vec<u8>> {
+    let Some(encryption) = &config.encryption else {
+        return Ok(bytes);
+    };
+    let (id, key) = encryption.keys.current()?;
+    let id_len: u8 = u8::try_from(id.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "key ids are limited to 255 bytes"))?;
+
+    let mut header: Vec<u8> = MAGIC.to_vec();
+    header.push(encryption.cipher.id());
+    header.push(id_len);
+    header.extend_from_slice(id.as_bytes());
+    let check_nonce: [u8; NONCE_LEN] = nonce();
+    let check: Vec<u8> = encryption.cipher.seal(&key, &check_nonce, &header, &[])?;
+    header.extend_from_slice(&check_nonce);
+    header.extend_from_slice(&check);
+    let body_nonce: [u8; NONCE_LEN] = nonce();
+    header.extend_from_slice(&body_nonce);
+
+    let body: Vec<u8> = encryption.cipher.seal(&key, &body_nonce, &header, &bytes)?;
+    header.extend_from_slice(&body);
+    Ok(header)
+}
+
+#[cfg(not(feature = "encryption"))]
+pub(crate) fn encrypt(bytes: Vec<u8>, _config: &Config) -> io::Result<Vec<u8>> {
+    Ok(bytes)
+}
+
+/// Decrypts a file read from `path` if it is encrypted, passing plain files through.
+///
+/// Fails with [`MemoError::WrongKey`] if the key isn't available or doesn't match, and with
+/// `InvalidData` if the file is damaged.
+#[cfg(feature = "encryption")]
+pub(crate) fn decrypt(bytes: Vec<u8>, config: &Config, path: &Path) -> io::Result<Vec<u8>> {
+    if !bytes.starts_with(&MAGIC) {
+        return Ok(bytes);
+    }
+    let damaged = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("encrypted file {reason}"));
+    let rest: &[u8] = &bytes[MAGIC.len()..];
+    let [cipher, id_len, rest @ ..] = rest else {
+        return Err(damaged("header is truncated"));
+    };
+    let cipher: Cipher = Cipher::from_id(*cipher).ok_or_else(|| damaged("uses an unknown cipher"))?;
+    let id_len: usize = usize::from(*id_len);
+    if rest.len() < id_len + 2 * NONCE_LEN + TAG_LEN {
+        return Err(damaged("header is truncated"));
+    }
+    let id: String = String::from_utf8_lossy(&rest[..id_len]).into_owned();
+    let (check_nonce, rest) = rest[id_len..].split_at(NONCE_LEN);
+    let (check, rest) = rest.split_at(TAG_LEN);
+    let (body_nonce, body) = rest.split_at(NONCE_LEN);
+    let key_end: usize = MAGIC.len() + 2 + id_len;
+    let header_end: usize = bytes.len() - body.len();
+
+    let wrong_key = || MemoError::WrongKey { path: path.to_path_buf(), key: id.clone() };
+    let key: [u8; 32] = match &config.encryption {
+        Some(encryption) => encryption.keys.get(&id)?.ok_or_else(wrong_key)?,
+        None => return Err(wrong_key().into()),
+    };
+    if cipher.open(&key, check_nonce, &bytes[..key_end], check).is_none() {
+        return Err(wrong_key().into());
+    }
+    cipher
+        .open(&key, body_nonce, &bytes[..header_end], body)
+        .ok_or_else(|| damaged("failed to authenticate"))
+}
+
+#[cfg(not(feature = "encryption"))]
+pub(crate) fn decrypt(bytes: Vec<u8>, _config: &Config, _path: &Path) -> io::Result<Vec<u8>> {
+    if bytes.starts_with(&MAGIC) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "file is encrypted but memorable was built without the `encryption` feature",
+        ));
+    }
+    Ok(bytes)
+}
+
+#[cfg(feature = "encryption")]
+fn nonce() -> [u8; NONCE_LEN] {
+    let mut nonce: [u8; NONCE_LEN] = [0; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
@@ -0,0 +1,179 @@
+use std::{fs::{self, File}, io::{self, ErrorKind}, path::PathBuf};
+
+use crate::{disk::{self, SyncLevel}, lock, LockGuard, LockMode, Storage, Usage};
+
+/// Extension of every document file in a [`StorageMode::Directory`](crate::StorageMode::Directory) database.
+const EXTENSION: &str = ".json";
+
+/// Names Windows reserves regardless of extension, compared case-insensitively.
+const RESERVED: [&str; 22] = [
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
+    "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Longest file name a document may get: the temp file written next to it adds a dot, a uuid and
+/// `.tmp` to it, and most filesystems allow 255 bytes.
+const MAX_NAME: usize = 255 - 38;
+
+/// Turns a key into a file name that is valid on every platform.
+///
+/// ASCII letters, digits, `-` and `_` are kept, as is `.` except at the start. Every other byte of
+/// the id's utf-8 is written as `%XX`, and so is the first character of a name Windows reserves.
+fn escape(id: &str) -> String {
+    let stem: &str = id.split('.').next().unwrap_or_default();
+    let reserved: bool = RESERVED.contains(&stem.to_ascii_lowercase().as_str());
+    let mut name: String = String::with_capacity(id.len() + EXTENSION.len());
+    for (i, b) in id.bytes().enumerate() {
+        let keep: bool = match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => !(i == 0 && reserved),
+            b'.' => i > 0,
+            _ => false,
+        };
+        if keep {
+            name.push(b as char);
+        } else {
+            name.push_str(&format!("%{b:02X}"));
+        }
+    }
+    name.push_str(EXTENSION);
+    name
+}
+
+/// Reverses [`escape`], `None` if `name` isn't the file name of a document.
+fn unescape(name: &str) -> Option<String> {
+    let stem: &[u8] = name.strip_suffix(EXTENSION)?.as_bytes();
+    if stem.is_empty() || stem[0] == b'.' {
+        return None;
+    }
+    let mut id: Vec<u8> = Vec::with_capacity(stem.len());
+    let mut i: usize = 0;
+    while i < stem.len() {
+        if stem[i] == b'%' {
+            let hex: &str = std::str::from_utf8(stem.get(i + 1..i + 3)?).ok()?;
+            id.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            id.push(stem[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(id).ok()
+}
+
+#[doc = r#"A [`Storage`] keeping every object in its own file `<dir>/<key>.json`, used by [`StorageMode::Directory`](crate::StorageMode::Directory).
+
+Keys are escaped into file names that are valid on every platform: ASCII letters, digits, `-`, `_`
+and any `.` but a leading one are kept, every other byte is written as `%XX`. Keys that only differ
+in case share a file on case-insensitive filesystems, such as the defaults of macOS and Windows.
+A key whose file name would pass 217 bytes is refused with [`ErrorKind::InvalidInput`]. The
+directory is created by the first write, and the lock is taken on `<dir>.lock`. The empty key stands
+for the directory itself."#]
+#[derive(Debug, Clone)]
+pub struct DirStorage {
+    dir: PathBuf,
+}
+
+impl DirStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        let name: String = match key {
+            "" => return Ok(self.dir.clone()),
+            key => escape(key),
+        };
+        if name.len() > MAX_NAME {
+            let reason: String = format!("id {key:?} is {} bytes long as a file name, more than the {MAX_NAME} allowed", name.len());
+            return Err(io::Error::new(ErrorKind::InvalidInput, reason));
+        }
+        Ok(self.dir.join(name))
+    }
+}
+
+impl Storage for DirStorage {
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        disk::read(&self.path(key)?)
+    }
+
+    fn read_range(&self, key: &str, offset: u64, len: usize) -> io::Result<Option<Vec<u8>>> {
+        disk::read_range(&self.path(key)?, offset, len)
+    }
+
+    fn write(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+        if !self.dir.exists() {
+            fs::create_dir_all(&self.dir)?;
+            level.sync_dir(&self.dir)?;
+        }
+        disk::write_atomic(&self.path(key)?, bytes, level)
+    }
+
+    fn append(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        disk::append(&self.path(key)?, bytes, level)
+    }
+
+    fn remove(&self, key: &str, level: SyncLevel) -> io::Result<()> {
+        disk::remove(&self.path(key)?, level)
+    }
+
+    /// Temp files and backups don't end in `.json` and are skipped.
+    fn list(&self) -> io::Result<Vec<String>> {
+        let entries: fs::ReadDir = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut keys: Vec<String> = Vec::new();
+        for entry in entries {
+            if let Some(key) = entry?.file_name().to_str().and_then(unescape) {
+                keys.push(key);
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn lock(&self, exclusive: bool, mode: LockMode) -> io::Result<Option<LockGuard>> {
+        let guard: Option<lock::FileLock> = lock::acquire(&disk::sidecar(&self.dir, "lock"), exclusive, mode)?;
+        Ok(guard.map(|g| Box::new(g) as LockGuard))
+    }
+
+    fn fingerprint(&self, key: &str) -> io::Result<Option<u64>> {
+        disk::fingerprint(&self.path(key)?)
+    }
+
+    fn move_aside(&self, key: &str, suffix: &str) -> io::Result<PathBuf> {
+        disk::backup(&self.path(key)?, suffix)
+    }
+
+    fn copy_aside(&self, key: &str, suffix: &str) -> io::Result<Option<PathBuf>> {
+        let path: PathBuf = self.path(key)?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+        disk::backup_copy(&path, suffix).map(Some)
+    }
+
+    fn locate(&self, key: &str) -> PathBuf {
+        match key {
+            "" => self.dir.clone(),
+            key => self.dir.join(escape(key)),
+        }
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        for key in self.list()? {
+            File::open(self.path(&key)?)?.sync_all()?;
+        }
+        if self.dir.exists() {
+            File::open(&self.dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn usage(&self) -> io::Result<Usage> {
+        let total: u64 = disk::size(self.list()?.iter().map(|key| self.locate(key)))?;
+        Ok(Usage { total, live: total })
+    }
+}
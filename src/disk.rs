@@ -0,0 +1,245 @@
+use std::{fs::{self, File}, hash::{DefaultHasher, Hash, Hasher}, io::{self, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+/// How much of a write a [`Storage`](crate::Storage) forces to the device before returning,
+/// derived from [`Durability`](crate::Durability).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncLevel {
+    /// Leave flushing to the OS.
+    None,
+    /// Sync the written data, like `File::sync_data`.
+    Data,
+    /// Sync the data, its metadata and the directory entry, like `File::sync_all` plus a directory fsync.
+    All,
+}
+
+impl SyncLevel {
+    /// Syncs `file` as much as the level asks for.
+    pub fn sync_file(self, file: &File) -> io::Result<()> {
+        match self {
+            SyncLevel::None => Ok(()),
+            SyncLevel::Data => file.sync_data(),
+            SyncLevel::All => file.sync_all(),
+        }
+    }
+
+    /// Fsyncs the directory containing `path` with [`SyncLevel::All`].
+    pub fn sync_dir(self, path: &Path) -> io::Result<()> {
+        match self {
+            SyncLevel::All => sync_dir(path),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Replaces the contents of `path` with `bytes` so that a crash at any point leaves either the
+/// old or the new complete file on disk, never a mix of both.
+///
+/// The data is written to a sibling temp file, synced according to `level`, renamed over `path`,
+/// and with [`SyncLevel::All`] the parent directory is fsynced so the rename itself survives a
+/// power loss.
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+    write_atomic_with(path, level, &mut |file| file.write_all(bytes))
+}
+
+/// [`write_atomic`] with the bytes written by `fill` straight into the temp file. `path` is left
+/// untouched if `fill` fails.
+pub(crate) fn write_atomic_with(path: &Path, level: SyncLevel, fill: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+    let tmp: PathBuf = temp_path(path);
+    let written: io::Result<()> = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&tmp)
+        .and_then(|file| {
+            let mut out: BufWriter<File> = BufWriter::new(file);
+            fill(&mut out)?;
+            let file: File = out.into_inner().map_err(io::IntoInnerError::into_error)?;
+            level.sync_file(&file)
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    level.sync_dir(path)
+}
+
+/// Reads the whole file at `path`, `None` if it doesn't exist.
+pub(crate) fn read(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(b) => Ok(Some(b)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads up to `len` bytes of the file at `path` from `offset` on, `None` if it doesn't exist.
+pub(crate) fn read_range(path: &Path, offset: u64, len: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut file: File = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buff: Vec<u8> = Vec::new();
+    file.take(len as u64).read_to_end(&mut buff)?;
+    Ok(Some(buff))
+}
+
+/// The summed length of the files at `paths`, skipping the ones that don't exist.
+pub(crate) fn size(paths: impl Iterator<Item = PathBuf>) -> io::Result<u64> {
+    let mut total: u64 = 0;
+    for path in paths {
+        match fs::metadata(path) {
+            Ok(meta) => total += meta.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(total)
+}
+
+/// Appends `bytes` to `path`, creating it if needed, in a single write.
+pub(crate) fn append(path: &Path, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+    let created: bool = !path.exists();
+    let mut file: File = File::options().create(true).append(true).open(path)?;
+    file.write_all(bytes)?;
+    level.sync_file(&file)?;
+    if created {
+        level.sync_dir(path)?;
+    }
+    Ok(())
+}
+
+/// Removes `path` if it exists.
+pub(crate) fn remove(path: &Path, level: SyncLevel) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => level.sync_dir(path),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Identifies one version of a file on disk, used to notice writes made by other processes.
+///
+/// Every atomic write creates a new inode, and journal appends change the length, so the pair
+/// of inode (on unix) and length catches them even where mtime is coarse.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Fingerprint {
+    len: u64,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    ino: u64,
+}
+
+impl Fingerprint {
+    /// Returns the fingerprint of `path`, `None` if it doesn't exist.
+    pub(crate) fn of(path: &Path) -> io::Result<Option<Fingerprint>> {
+        let meta: fs::Metadata = match fs::metadata(path) {
+            Ok(m) => m,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(Fingerprint {
+            len: meta.len(),
+            modified: meta.modified().ok(),
+            #[cfg(unix)]
+            ino: std::os::unix::fs::MetadataExt::ino(&meta),
+        }))
+    }
+}
+
+/// Hash of the [`Fingerprint`] of `path`, `None` if it doesn't exist.
+pub(crate) fn fingerprint(path: &Path) -> io::Result<Option<u64>> {
+    Ok(Fingerprint::of(path)?.map(|f| {
+        let mut hasher: DefaultHasher = DefaultHasher::new();
+        f.hash(&mut hasher);
+        hasher.finish()
+    }))
+}
+
+/// Moves `path` aside to `<path>.<unix millis>.<suffix>` and returns the new location.
+pub(crate) fn backup(path: &Path, suffix: &str) -> io::Result<PathBuf> {
+    let dest: PathBuf = timestamped(path, suffix);
+    fs::rename(path, &dest)?;
+    sync_dir(path)?;
+    Ok(dest)
+}
+
+/// Copies `path` to `<path>.<unix millis>.<suffix>`, fsynced, and returns the copy's location.
+pub(crate) fn backup_copy(path: &Path, suffix: &str) -> io::Result<PathBuf> {
+    let dest: PathBuf = timestamped(path, suffix);
+    fs::copy(path, &dest)?;
+    File::open(&dest)?.sync_all()?;
+    sync_dir(&dest)?;
+    Ok(dest)
+}
+
+/// Returns `<path>.<unix millis>.<suffix>`.
+pub(crate) fn timestamped(path: &Path, suffix: &str) -> PathBuf {
+    let millis: u128 = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis());
+    sidecar(path, &format!("{millis}.{suffix}"))
+}
+
+/// Removes all but the `keep` newest `<name>.<unix millis>.<suffix>` files in `dir`.
+pub(crate) fn prune(dir: &Path, name: &str, suffix: &str, keep: usize) -> io::Result<()> {
+    let mut stamped: Vec<(u128, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry: fs::DirEntry = entry?;
+        let file_name = entry.file_name();
+        let millis: Option<u128> = file_name
+            .to_str()
+            .and_then(|n| n.strip_prefix(name))
+            .and_then(|n| n.strip_prefix('.'))
+            .and_then(|n| n.strip_suffix(suffix))
+            .and_then(|n| n.strip_suffix('.'))
+            .and_then(|n| n.parse().ok());
+        if let Some(millis) = millis {
+            stamped.push((millis, entry.path()));
+        }
+    }
+    stamped.sort();
+    let stale: usize = stamped.len().saturating_sub(keep);
+    for (_, path) in stamped.drain(..stale) {
+        fs::remove_file(path)?;
+    }
+    sync_dir(&dir.join(name))
+}
+
+/// Returns `<path>.<suffix>`.
+pub(crate) fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{suffix}"));
+    PathBuf::from(name)
+}
+
+pub(crate) fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The directory containing `path`, `.` for a bare file name.
+pub(crate) fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+/// Temp files live next to the target so the final `rename` never crosses a filesystem.
+fn temp_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(".{}.{}.tmp", file_name(path), uuid::Uuid::new_v4().simple()))
+}
+
+/// Fsyncs the directory containing `path`, making a created, renamed or removed entry durable.
+#[cfg(unix)]
+pub(crate) fn sync_dir(path: &Path) -> io::Result<()> {
+    File::open(parent(path))?.sync_all()
+}
+
+// Directories cannot be opened with `File::open` outside of unix, so the rename is left to the OS.
+#[cfg(not(unix))]
+pub(crate) fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::{error::Error, fmt, io::{self, ErrorKind}, path::PathBuf};
+
+#[doc = r#"Errors specific to memorable.
+
+Every fallible function still returns an `io::error::Error`; these are carried inside it and
+can be recovered with [`MemoError::from_io`].
+
+# Examples
+```
+use memorable::MemoError;
+use std::io;
+
+fn describe(err: &io::Error) -> String {
+    match MemoError::from_io(err) {
+        Some(MemoError::Corrupted { path, .. }) => format!("{} needs repair", path.display()),
+        _ => err.to_string(),
+    }
+}
+```"#]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum MemoError {
+    /// The file at `path` exists but its contents can't be parsed.
+    Corrupted { path: PathBuf, reason: String },
+    /// Another process holds the lock file at `path` and it could not be acquired.
+    Locked { path: PathBuf },
+    /// The file at `path` was changed by someone else since it was last read, see
+    /// [`ConflictPolicy::Error`](crate::ConflictPolicy::Error). A lazy lines file rewritten that way
+    /// returns it from [`DataBase::try_get`](crate::DataBase::try_get) regardless of the policy.
+    Conflict { path: PathBuf },
+    /// The file at `path` was written by a newer version of memorable.
+    UnsupportedFormat { path: PathBuf, version: u64 },
+    /// The file at `path` holds documents of a newer schema version than [`Config::schema_version`](crate::Config::schema_version).
+    NewerSchema { path: PathBuf, version: u32 },
+    /// Upgrading documents from schema `version` to the next one failed.
+    Migration { version: u32, reason: String },
+    /// The file at `path` is encrypted with the key `key`, which isn't available or doesn't match.
+    WrongKey { path: PathBuf, key: String },
+    /// The operation was valid but not applied, because operation `index` of its batch failed, see
+    /// [`BatchMode::AllOrNothing`](crate::BatchMode::AllOrNothing).
+    Aborted { index: usize },
+}
+
+impl MemoError {
+    /// Returns the `MemoError` wrapped in `err`, if any.
+    pub fn from_io(err: &io::Error) -> Option<&MemoError> {
+        err.get_ref()?.downcast_ref()
+    }
+
+    /// The `io::ErrorKind` used when this error is turned into an `io::Error`.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            MemoError::Corrupted { .. } => ErrorKind::InvalidData,
+            MemoError::Locked { .. } => ErrorKind::WouldBlock,
+            MemoError::Conflict { .. } => ErrorKind::Other,
+            MemoError::UnsupportedFormat { .. } | MemoError::NewerSchema { .. } => ErrorKind::Unsupported,
+            MemoError::Migration { .. } => ErrorKind::InvalidData,
+            MemoError::WrongKey { .. } => ErrorKind::PermissionDenied,
+            MemoError::Aborted { .. } => ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for MemoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoError::Corrupted { path, reason } => write!(f, "Database file ({}) is corrupt: {reason}", path.display()),
+            MemoError::Locked { path } => write!(f, "Database lock ({}) is held by another process.", path.display()),
+            MemoError::Conflict { path } => write!(f, "Database file ({}) was modified since it was last read.", path.display()),
+            MemoError::UnsupportedFormat { path, version } => {
+                write!(f, "Database file ({}) uses format version {version}, which this version of memorable can't read.", path.display())
+            },
+            MemoError::NewerSchema { path, version } => {
+                write!(f, "Database file ({}) holds documents of the newer schema version {version}.", path.display())
+            },
+            MemoError::Migration { version, reason } => write!(f, "Migration from schema version {version} failed: {reason}"),
+            MemoError::WrongKey { path, key } => {
+                write!(f, "Database file ({}) is encrypted with key ({key}), which isn't available or doesn't match.", path.display())
+            },
+            MemoError::Aborted { index } => write!(f, "Not applied, as operation {index} of the batch failed."),
+        }
+    }
+}
+
+impl Error for MemoError {}
+
+impl From<MemoError> for io::Error {
+    fn from(err: MemoError) -> Self {
+        io::Error::new(err.kind(), err)
+    }
+}
@@ -0,0 +1,227 @@
+use std::{collections::HashMap, fmt, io, time::{SystemTime, UNIX_EPOCH}};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::Codec;
+
+/// Version of the envelope written by this crate. Files with a higher version are refused.
+pub(crate) const FORMAT_VERSION: u64 = 1;
+
+/// The wrapper around the stored map, written unless turned off, see [`Config::envelope`](crate::Config::envelope).
+#[derive(Serialize, Deserialize)]
+struct Envelope<D> {
+    #[serde(rename = "$memorable")]
+    format: u64,
+    #[serde(default)]
+    schema: u32,
+    #[serde(default)]
+    created: u64,
+    #[serde(default)]
+    modified: u64,
+    count: usize,
+    checksum: String,
+    docs: D,
+}
+
+#[doc = r#"Metadata stored in the envelope of a database file, see [`DataBase::header`](crate::DataBase::header).
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, MemoDoc, MemoError};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_header.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let config = Config { schema_version: 3, ..Config::default() };
+    let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+    let created = f.header().unwrap().created;
+    f.push(Task::default()).await.unwrap();
+
+    let header = DataBase::<Task>::open_with(path, config).unwrap().header().copied().unwrap();
+    assert_eq!(header.schema, 3);
+    assert_eq!(header.created, created);
+    assert!(header.modified >= created);
+
+    // Files from a newer version of memorable are refused rather than misread.
+    let newer = std::fs::read_to_string(path).unwrap().replace("\"$memorable\": 1", "\"$memorable\": 99");
+    std::fs::write(path, newer).unwrap();
+    let err = DataBase::<Task>::open(path).unwrap_err();
+    assert!(matches!(MemoError::from_io(&err), Some(MemoError::UnsupportedFormat { version: 99, .. })));
+    # });
+}
+```"#]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Version of memorable's file format that wrote the file.
+    pub format: u64,
+    /// The [`Config::schema_version`](crate::Config::schema_version) of the documents.
+    pub schema: u32,
+    /// When the file was first written, in milliseconds since the unix epoch.
+    pub created: u64,
+    /// When the file was last written, in milliseconds since the unix epoch.
+    pub modified: u64,
+}
+
+impl Header {
+    /// The header for a file written now, keeping `previous.created` if there is one.
+    pub(crate) fn next(previous: Option<&Header>, schema: u32) -> Header {
+        let now: u64 = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        Header {
+            format: FORMAT_VERSION,
+            schema,
+            created: previous.map_or(now, |h| h.created),
+            modified: now,
+        }
+    }
+}
+
+#[doc = r#"A single problem found by [`DataBase::verify`](crate::DataBase::verify)."#]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IntegrityError {
+    /// The file can't be decoded by the configured [`Codec`], e.g. it was truncated.
+    Unreadable { reason: String },
+    /// The envelope was written by a newer version of memorable.
+    UnsupportedFormat { version: u64 },
+    /// The envelope's header doesn't have the expected fields.
+    InvalidEnvelope { reason: String },
+    /// The envelope's document count doesn't match the documents stored.
+    CountMismatch { header: usize, found: usize },
+    /// The envelope's checksum doesn't match the documents stored.
+    ChecksumMismatch { header: String, computed: String },
+    /// The document stored under `id` can't be de-serialized.
+    InvalidDocument { id: String, reason: String },
+    /// The document stored under `key` returns a different `get_id()`.
+    IdMismatch { key: String, id: String },
+    /// A journal record can't be de-serialized.
+    InvalidRecord { offset: usize, reason: String },
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityError::Unreadable { reason } => write!(f, "file can't be decoded: {reason}"),
+            IntegrityError::UnsupportedFormat { version } => write!(f, "format version {version} is newer than {FORMAT_VERSION}"),
+            IntegrityError::InvalidEnvelope { reason } => write!(f, "invalid envelope: {reason}"),
+            IntegrityError::CountMismatch { header, found } => write!(f, "header counts {header} documents but {found} are stored"),
+            IntegrityError::ChecksumMismatch { header, computed } => write!(f, "header checksum {header} but documents hash to {computed}"),
+            IntegrityError::InvalidDocument { id, reason } => write!(f, "document ({id}) is invalid: {reason}"),
+            IntegrityError::IdMismatch { key, id } => write!(f, "document stored under ({key}) has id ({id})"),
+            IntegrityError::InvalidRecord { offset, reason } => write!(f, "journal record at byte {offset} is invalid: {reason}"),
+        }
+    }
+}
+
+/// Encodes `docs` with `codec`, wrapped in an envelope carrying `header` if there is one.
+pub(crate) fn encode<T: Serialize>(docs: &HashMap<String, T>, header: Option<&Header>, codec: &dyn Codec) -> io::Result<Vec<u8>> {
+    let docs: Value = serde_json::to_value(docs)?;
+    let Some(header) = header else {
+        return codec.encode(&docs);
+    };
+    codec.encode(&serde_json::to_value(Envelope {
+        format: header.format,
+        schema: header.schema,
+        created: header.created,
+        modified: header.modified,
+        count: docs.as_object().map_or(0, Map::len),
+        checksum: checksum(&docs)?,
+        docs,
+    })?)
+}
+
+/// A file's documents before they are de-serialized into `T`.
+pub(crate) struct Parsed {
+    pub(crate) docs: Map<String, Value>,
+    pub(crate) header: Option<Header>,
+    /// Problems with the envelope that still leave the documents readable.
+    pub(crate) problems: Vec<IntegrityError>,
+}
+
+impl Parsed {
+    /// De-serializes every document, failing with the first problem found.
+    pub(crate) fn into_docs<T: DeserializeOwned>(self) -> Result<HashMap<String, T>, IntegrityError> {
+        if let Some(problem) = self.problems.into_iter().next() {
+            return Err(problem);
+        }
+        self.docs
+            .into_iter()
+            .map(|(id, doc)| match serde_json::from_value::<T>(doc) {
+                Ok(doc) => Ok((id, doc)),
+                Err(e) => Err(IntegrityError::InvalidDocument { id, reason: e.to_string() }),
+            })
+            .collect()
+    }
+}
+
+/// Parses a plain or enveloped file into its untyped documents, checking the envelope.
+pub(crate) fn parse(bytes: &[u8], codec: &dyn Codec) -> Result<Parsed, IntegrityError> {
+    let value: Value = codec.decode(bytes).map_err(|e| IntegrityError::Unreadable { reason: e.to_string() })?;
+    let mut problems: Vec<IntegrityError> = Vec::new();
+    let mut header: Option<Header> = None;
+    let docs: Value = if value.get("$memorable").is_some() {
+        let envelope: Envelope<Value> = serde_json::from_value(value).map_err(|e| IntegrityError::InvalidEnvelope { reason: e.to_string() })?;
+        if envelope.format > FORMAT_VERSION {
+            return Err(IntegrityError::UnsupportedFormat { version: envelope.format });
+        }
+        header = Some(Header {
+            format: envelope.format,
+            schema: envelope.schema,
+            created: envelope.created,
+            modified: envelope.modified,
+        });
+        let stored: usize = envelope.docs.as_object().map_or(0, Map::len);
+        if envelope.count != stored {
+            problems.push(IntegrityError::CountMismatch { header: envelope.count, found: stored });
+        }
+        match checksum(&envelope.docs) {
+            Ok(computed) if computed != envelope.checksum => {
+                problems.push(IntegrityError::ChecksumMismatch { header: envelope.checksum, computed });
+            },
+            Ok(_) => {},
+            Err(e) => problems.push(IntegrityError::InvalidEnvelope { reason: e.to_string() }),
+        }
+        envelope.docs
+    } else {
+        value
+    };
+
+    match docs {
+        Value::Object(docs) => Ok(Parsed { docs, header, problems }),
+        _ => Err(IntegrityError::InvalidEnvelope { reason: "documents are not a json object".to_string() }),
+    }
+}
+
+/// Checks a plain or enveloped file, handing every valid document to `found` and returning the
+/// header along with all problems.
+pub(crate) fn check<T: DeserializeOwned>(
+    bytes: &[u8],
+    codec: &dyn Codec,
+    mut found: impl FnMut(&str, T),
+) -> (Option<Header>, Vec<IntegrityError>) {
+    let Parsed { docs, header, mut problems } = match parse(bytes, codec) {
+        Ok(parsed) => parsed,
+        Err(problem) => return (None, vec![problem]),
+    };
+    for (id, doc) in docs {
+        match serde_json::from_value::<T>(doc) {
+            Ok(doc) => found(&id, doc),
+            Err(e) => problems.push(IntegrityError::InvalidDocument { id, reason: e.to_string() }),
+        }
+    }
+    (header, problems)
+}
+
+/// CRC-32 of the documents' compact json. `serde_json::Map` keeps keys sorted, so the bytes, and
+/// with them the checksum, don't depend on `HashMap` iteration order.
+fn checksum(docs: &Value) -> serde_json::Result<String> {
+    Ok(format!("crc32:{:08x}", crc32fast::hash(&serde_json::to_vec(docs)?)))
+}
@@ -0,0 +1,188 @@
+use std::{collections::HashMap, io, path::Path};
+use serde::{de::{DeserializeOwned, IgnoredAny}, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{MemoError, Storage};
+
+/// A single mutation as it is stored in the journal, one json object per line.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub(crate) enum Record<D> {
+    Put { id: String, doc: D },
+    Del { id: String },
+    /// The next `count` records were appended together, and only count if all of them are complete.
+    Begin { count: usize },
+}
+
+/// Holds back the records of a batch until all of them are read.
+struct Batch<D> {
+    /// Offset of the `begin` record of the open batch and how many of its records are still missing.
+    open: Option<(u64, usize)>,
+    held: Vec<(u64, usize, Record<D>)>,
+}
+
+impl<D> Batch<D> {
+    fn new() -> Self {
+        Self { open: None, held: Vec::new() }
+    }
+
+    /// Takes the record of `len` bytes at `offset` and yields the records that are complete now.
+    fn take(&mut self, offset: u64, len: usize, record: Record<D>) -> std::vec::Drain<'_, (u64, usize, Record<D>)> {
+        match record {
+            Record::Begin { count } => {
+                self.open = (count > 0).then_some((offset, count));
+                return self.held.drain(..0);
+            },
+            record => self.held.push((offset, len, record)),
+        }
+        if let Some((_, missing)) = &mut self.open {
+            *missing -= 1;
+            if *missing > 0 {
+                return self.held.drain(..0);
+            }
+            self.open = None;
+        }
+        self.held.drain(..)
+    }
+
+    /// Where the log ends given its complete lines end at `complete`: a batch cut short by a crash
+    /// is as torn as a line without its newline.
+    fn end(&self, complete: u64) -> u64 {
+        self.open.map_or(complete, |(begin, _)| begin)
+    }
+}
+
+/// Key of the journal in the database's [`Storage`](crate::Storage).
+pub(crate) const KEY: &str = "journal";
+
+/// Upgrades a document to the current schema version, see [`Migrations`](crate::Migrations).
+pub(crate) type Upgrade<'a> = &'a dyn Fn(&mut Value) -> io::Result<()>;
+
+/// Applies every complete record of the log stored under `key` on top of `docs` and returns the
+/// length in bytes of those records, and whether anything follows them. Documents are passed
+/// through `upgrade` first if it is given, and where their records are is kept in `offsets` if it
+/// is given.
+///
+/// Replaying is idempotent, so a journal that was already folded into the snapshot (a crash
+/// during compaction) yields the same documents. A trailing line without its newline is the
+/// remains of an interrupted append; it was never acknowledged, so it is skipped, and so is a batch
+/// missing any of its records. Nothing is written: the torn tail is left for the next append to cut
+/// off, under the exclusive lock.
+pub(crate) fn replay<T: DeserializeOwned>(
+    storage: &dyn Storage,
+    key: &str,
+    docs: &mut HashMap<String, T>,
+    upgrade: Option<Upgrade>,
+    offsets: Option<&mut Offsets>,
+) -> io::Result<(u64, bool)> {
+    let Some(bytes) = storage.read(key)? else {
+        return Ok((0, false));
+    };
+    let complete: u64 = apply(&bytes, &storage.locate(key), docs, upgrade, offsets)?;
+    Ok((complete, complete < bytes.len() as u64))
+}
+
+/// Where the latest `put` record of every live document is in a log, as its offset and length without the newline.
+pub(crate) type Offsets = HashMap<String, (u64, usize)>;
+
+/// Bytes of the log read at a time by [`index`].
+const CHUNK: usize = 1024 * 1024;
+
+/// Like [`replay`], but only records where each document is instead of de-serializing it. The log
+/// is read in chunks, so it never has to fit in memory.
+pub(crate) fn index(storage: &dyn Storage, key: &str, offsets: &mut Offsets) -> io::Result<(u64, bool)> {
+    let path = storage.locate(key);
+    let (mut pos, mut start): (u64, u64) = (0, 0);
+    let mut pending: Vec<u8> = Vec::new();
+    let mut batch: Batch<IgnoredAny> = Batch::new();
+    loop {
+        let chunk: Vec<u8> = match storage.read_range(key, pos, CHUNK)? {
+            Some(chunk) if !chunk.is_empty() => chunk,
+            None if pos == 0 => return Ok((0, false)),
+            _ => break,
+        };
+        pos += chunk.len() as u64;
+        pending.extend_from_slice(&chunk);
+        for (at, line) in lines(&pending) {
+            let offset: u64 = start + at as u64;
+            let record: Record<IgnoredAny> = serde_json::from_slice(line).map_err(|e| MemoError::Corrupted {
+                path: path.clone(),
+                reason: format!("record at byte {offset}: {e}"),
+            })?;
+            for (offset, len, record) in batch.take(offset, line.len(), record) {
+                match record {
+                    Record::Put { id, .. } => offsets.insert(id, (offset, len)),
+                    Record::Del { id } => offsets.remove(&id),
+                    Record::Begin { .. } => None,
+                };
+            }
+        }
+        let complete: usize = complete_len(&pending);
+        start += complete as u64;
+        pending.drain(..complete);
+    }
+    let end: u64 = batch.end(start);
+    Ok((end, !pending.is_empty() || end < start))
+}
+
+/// Applies the complete records of `bytes`, read from `path`, and returns their length.
+fn apply<T: DeserializeOwned>(
+    bytes: &[u8],
+    path: &Path,
+    docs: &mut HashMap<String, T>,
+    upgrade: Option<Upgrade>,
+    mut offsets: Option<&mut Offsets>,
+) -> io::Result<u64> {
+    let mut batch: Batch<T> = Batch::new();
+    for (start, line) in lines(bytes) {
+        let corrupted = |e: serde_json::Error| MemoError::Corrupted {
+            path: path.to_path_buf(),
+            reason: format!("record at byte {start}: {e}"),
+        };
+        let record: Record<T> = match upgrade {
+            None => serde_json::from_slice(line).map_err(corrupted)?,
+            Some(upgrade) => match serde_json::from_slice::<Record<Value>>(line).map_err(corrupted)? {
+                Record::Put { id, mut doc } => {
+                    upgrade(&mut doc)?;
+                    Record::Put { id, doc: serde_json::from_value(doc).map_err(corrupted)? }
+                },
+                Record::Del { id } => Record::Del { id },
+                Record::Begin { count } => Record::Begin { count },
+            },
+        };
+        for (start, len, record) in batch.take(start as u64, line.len(), record) {
+            if let Some(offsets) = offsets.as_deref_mut() {
+                match &record {
+                    Record::Put { id, .. } => offsets.insert(id.clone(), (start, len)),
+                    Record::Del { id } => offsets.remove(id),
+                    Record::Begin { .. } => None,
+                };
+            }
+            match record {
+                Record::Put { id, doc } => {
+                    docs.insert(id, doc);
+                },
+                Record::Del { id } => {
+                    docs.remove(&id);
+                },
+                Record::Begin { .. } => {},
+            }
+        }
+    }
+    Ok(batch.end(complete_len(bytes) as u64))
+}
+
+/// Length of the journal up to and including its last newline.
+pub(crate) fn complete_len(bytes: &[u8]) -> usize {
+    bytes.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1)
+}
+
+/// Yields every complete, non-empty record line with its byte offset.
+pub(crate) fn lines(bytes: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    let mut offset: usize = 0;
+    bytes[..complete_len(bytes)].split(|b| *b == b'\n').filter_map(move |line| {
+        let start: usize = offset;
+        offset += line.len() + 1;
+        (!line.is_empty()).then_some((start, line))
+    })
+}
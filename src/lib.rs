@@ -1,7 +1,58 @@
-use std::{collections::HashMap, fs::File, io::{self, ErrorKind, Read, Seek, Write}};
+use std::{collections::{BTreeMap, HashMap}, fs, io::{self, ErrorKind, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}, sync::Arc, time::Instant};
 use std::io::Error as StdError;
 use serde::{Deserialize, Serialize};
 
+use cache::Cache;
+
+mod batch;
+mod bitcask;
+mod cache;
+mod codec;
+mod compress;
+mod config;
+mod crypto;
+mod dir;
+mod disk;
+mod error;
+mod format;
+mod journal;
+mod lock;
+mod memory;
+mod migrate;
+#[cfg(feature = "patch")]
+mod patch;
+mod storage;
+pub mod testing;
+mod transaction;
+
+pub use batch::{BatchMode, Op};
+pub use bitcask::BitcaskStorage;
+pub use codec::{Codec, JsonCompact, JsonPretty};
+#[cfg(feature = "bincode")]
+pub use codec::Bincode;
+#[cfg(feature = "cbor")]
+pub use codec::Cbor;
+#[cfg(feature = "msgpack")]
+pub use codec::MessagePack;
+#[cfg(feature = "ron")]
+pub use codec::Ron;
+pub use compress::Compression;
+#[cfg(feature = "encryption")]
+pub use crypto::{Cipher, Encryption, KeyProvider, KeyRing};
+pub use config::{AutoCompact, Config, ConflictPolicy, CorruptionPolicy, Durability, LockMode, StorageMode};
+pub use error::MemoError;
+pub use format::{Header, IntegrityError};
+pub use dir::DirStorage;
+pub use disk::SyncLevel;
+pub use memory::MemoryStorage;
+pub use migrate::Migrations;
+#[cfg(feature = "patch")]
+pub use patch::Patch;
+pub use storage::{Compaction, FileStorage, LockGuard, ReadSeek, Storage, Usage};
+pub use transaction::Transaction;
+
+/// How many errors [`DataBase::take_errors`] keeps.
+const DEFERRED: usize = 16;
 
 #[doc = r#"Trait necessary to push a doc to the database.
 # Implementation
@@ -31,87 +82,320 @@ pub trait MemoDoc {
 expose the `tasks vector` for eazy editability.
 # Examples
 ```
-use memorable::DataBase;
-use memorable::memorable_macro_derive::MemoDoc;
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+use std::collections::HashMap;
 
-#[derive(MemoDoc, Serialize, Deserialize)]
+#[derive(MemoDoc, Serialize, Deserialize, Clone)]
 struct Task {
     uuid: String
 }
 
 fn main() {
-    let f = DataBase::open("./db.json").unwrap();
-    let tasks: Vec<Task> = f.docs;
+    # let path = std::env::temp_dir().join("memorable_doc_database.json");
+    # let path = path.to_str().unwrap();
+    let f: DataBase<Task> = DataBase::open(path).unwrap();
+    let tasks: HashMap<String, Task> = f.docs;
 }
 ```"#]
 #[derive(Debug, Clone)]
 pub struct DataBase<T: Serialize + for<'de> Deserialize<'de> + MemoDoc + Clone> {
-    file_path: String,
-    pub docs: HashMap<String, T>
+    storage: Arc<dyn Storage>,
+    pub docs: HashMap<String, T>,
+    config: Config,
+    journal_len: u64,
+    /// Whether the log holds the remains of an interrupted append past `journal_len`, which the
+    /// next append cuts off.
+    torn: bool,
+    last_sync: Option<Instant>,
+    recovered: Vec<PathBuf>,
+    seen: [Option<u64>; 2],
+    header: Option<Header>,
+    /// Where every document is in a [`StorageMode::Lines`] file.
+    offsets: journal::Offsets,
+    /// Bytes of the lines `offsets` points at, with their newlines.
+    live_len: u64,
+    cache: Cache<T>,
+    /// Errors of follow-up work that didn't fail the call it followed, see [`DataBase::take_errors`].
+    deferred: Vec<(ErrorKind, String)>,
+}
+
+/// The snapshot file as read by `open`.
+struct Loaded<T> {
+    docs: HashMap<String, T>,
+    header: Option<Header>,
+    /// The schema version the documents were upgraded from, if they were.
+    migrated_from: Option<u32>,
 }
 
 impl<T: Serialize + for<'de> Deserialize<'de> + MemoDoc + Clone> DataBase<T> {
 
 #[doc = r#"Opens and fetches data from the `Tasks` database.
 
+A missing or empty file is created as an empty database. A file that can't be parsed is never
+overwritten, see [`CorruptionPolicy`].
+
 # Errors
 
 This function may throw an `error` due to a number of different reasons. Some of them are listed below:
     1. Function will return an `io::error::Error` if there is any problem locating or opening the database's json file.
-    2. Function will return an `serde_json::error:Error` if there is any problem serializing or de-serializing the data in the file.
+    2. Function will return an `serde_json::error:Error` if there is any problem serializing the data in the file.
+    3. Function will return a [`MemoError::Corrupted`] (kind `InvalidData`) if the file exists but can't be de-serialized, or fails its checksum.
+    4. Function will return a [`MemoError::Locked`] (kind `WouldBlock`) if the lock can't be taken with [`LockMode::Try`] or [`LockMode::Timeout`].
+    5. Function will return a [`MemoError::UnsupportedFormat`] (kind `Unsupported`) if the file was written by a newer version of memorable.
+    6. Function will return a [`MemoError::WrongKey`] (kind `PermissionDenied`) if the file is encrypted and the configured key can't decrypt it.
 
 # Examples
 ```
-use memorable::DataBase;
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
 fn main() {
-    let f = DataBase::open("./path.json").unwrap();
+    # let path = std::env::temp_dir().join("memorable_doc_open.json");
+    # let path = path.to_str().unwrap();
+    let f: DataBase<Task> = DataBase::open(path).unwrap();
     println!("Tasks: {:#?}", f.docs);
 }
 ```"#]
     pub fn open(path: &str) -> Result<DataBase<T>, StdError> {
-        let file: Option<File> = match File::open(path) {
-            Ok(f) => Some(f),
-            Err(e) => {
-                println!("Err: {e}");
-                None
-            }
-        };
+        Self::open_with(path, Config::default())
+    }
 
-        let buff: String = match file {
-            Some(mut f) => {
-                let mut data: String = String::new();
-                f.read_to_string(&mut data)?;
-                data
-            },
-            None => String::new()
+#[doc = r#"Opens the database like [`DataBase::open`], but recovers from a corrupt file or journal with [`CorruptionPolicy::Backup`].
+
+The broken file is moved to `<file>.<unix millis>.corrupt` and the database starts from what
+could still be read; the backups are listed by [`DataBase::recovered`].
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc, MemoError};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let dir = std::env::temp_dir().join("memorable_doc_open_recover");
+    # let _ = std::fs::remove_dir_all(&dir);
+    # std::fs::create_dir_all(&dir).unwrap();
+    # let path = dir.join("db.json");
+    # let path = path.to_str().unwrap();
+    std::fs::write(path, "{ \"half\": { \"uu").unwrap();
+
+    let err = DataBase::<Task>::open(path).unwrap_err();
+    assert!(matches!(MemoError::from_io(&err), Some(MemoError::Corrupted { .. })));
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "{ \"half\": { \"uu");
+
+    let f: DataBase<Task> = DataBase::open_recover(path).unwrap();
+    assert!(f.docs.is_empty());
+    let backup = &f.recovered()[0];
+    assert_eq!(std::fs::read_to_string(backup).unwrap(), "{ \"half\": { \"uu");
+}
+```"#]
+    pub fn open_recover(path: &str) -> Result<DataBase<T>, StdError> {
+        Self::open_with(path, Config { on_corruption: CorruptionPolicy::Backup, ..Config::default() })
+    }
+
+#[doc = r#"Opens the database like [`DataBase::open`] using the settings in `config`.
+
+With [`StorageMode::Journal`] the journal next to the file is replayed over the snapshot,
+and compacted right away if it is already past [`Config::journal_limit`].
+
+# Errors
+
+Same as [`DataBase::open`]. A journal record that can't be de-serialized is handled like a corrupt file.
+Encryption combined with any mode but [`StorageMode::Snapshot`], a schema version combined with
+[`StorageMode::Lines`], [`StorageMode::Directory`] or [`StorageMode::Bitcask`], and [`Config::lazy`]
+combined with [`StorageMode::Snapshot`] or [`StorageMode::Journal`] are refused with kind `InvalidInput`.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, MemoDoc, StorageMode};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_open_with.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # let _ = std::fs::remove_file(format!("{path}.journal"));
+    # pollster::block_on(async {
+    let config = Config { mode: StorageMode::Journal, ..Config::default() };
+    let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+    f.push(Task::default()).await.unwrap();
+
+    // The push only appended to `<path>.journal`, reopening replays it.
+    let f: DataBase<Task> = DataBase::open_with(path, config).unwrap();
+    assert_eq!(f.docs.len(), 1);
+    # });
+}
+```"#]
+    pub fn open_with(path: &str, config: Config) -> Result<DataBase<T>, StdError> {
+        let storage: Arc<dyn Storage> = match config.mode {
+            StorageMode::Directory => Arc::new(DirStorage::new(path)),
+            StorageMode::Bitcask => Arc::new(BitcaskStorage::new(path)),
+            _ => Arc::new(FileStorage::new(path)),
         };
+        Self::open_with_storage(storage, config)
+    }
 
-        let docs: HashMap<String, T> = match serde_json::from_str(&buff) {
-            Ok(t) => t,
-            Err(e) => {
-                println!("Err: {e}");
-                let op: HashMap<String, T> = HashMap::<String, T>::new();
-                File::create(path)?.write_all(
-                    serde_json::to_string_pretty(&op)?.as_bytes()
-                )?;
-                op
-            },
+#[doc = r#"Opens the database kept in `storage` like [`DataBase::open_with`], which uses a [`FileStorage`],
+a [`DirStorage`] for [`StorageMode::Directory`] or a [`BitcaskStorage`] for [`StorageMode::Bitcask`].
+
+See [`Storage`] for an example.
+
+# Errors
+
+Same as [`DataBase::open_with`].
+"#]
+    pub fn open_with_storage(storage: Arc<dyn Storage>, config: Config) -> Result<DataBase<T>, StdError> {
+        #[cfg(feature = "encryption")]
+        if config.encryption.is_some() && config.mode != StorageMode::Snapshot {
+            return Err(StdError::new(ErrorKind::InvalidInput, format!("encryption can't be combined with StorageMode::{:?}", config.mode)));
+        }
+        if config.schema_version > 0 && matches!(config.mode, StorageMode::Lines | StorageMode::Directory | StorageMode::Bitcask) {
+            return Err(StdError::new(ErrorKind::InvalidInput, format!("StorageMode::{:?} can't record a schema_version", config.mode)));
+        }
+        if config.lazy && matches!(config.mode, StorageMode::Snapshot | StorageMode::Journal) {
+            return Err(StdError::new(ErrorKind::InvalidInput, format!("StorageMode::{:?} reads the whole file, it can't be lazy", config.mode)));
+        }
+        let mut db: DataBase<T> = Self {
+            storage,
+            docs: HashMap::new(),
+            journal_len: 0,
+            torn: false,
+            last_sync: None,
+            recovered: Vec::new(),
+            seen: [None, None],
+            header: None,
+            offsets: HashMap::new(),
+            live_len: 0,
+            cache: Cache::new(config.cache),
+            deferred: Vec::new(),
+            config
         };
+        db.load_locked()?;
+        Ok(db)
+    }
+
+#[doc = r#"Creates an empty database that lives only in memory, with the same behaviour as one opened with [`DataBase::open`].
+
+Nothing is read from or written to the disk, except on demand: [`DataBase::snapshot`] dumps the
+database to a file and [`DataBase::restore`] loads one. To use other settings, open a
+[`MemoryStorage`] with [`DataBase::open_with_storage`].
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_in_memory.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut f: DataBase<Task> = DataBase::in_memory();
+    f.push(Task { uuid: "a".into() }).await.unwrap();
+    assert!(f.get("a").await.is_some());
+
+    f.snapshot(path).unwrap();
+    f.del("a").await.unwrap();
+    assert!(f.get("a").await.is_none());
+
+    f.restore(path).unwrap();
+    assert!(f.get("a").await.is_some());
+    assert_eq!(DataBase::<Task>::open(path).unwrap().docs.len(), 1);
+    # });
+}
+```"#]
+    pub fn in_memory() -> DataBase<T> {
+        Self::open_with_storage(Arc::new(MemoryStorage::new()), Config::default())
+            .expect("an empty in-memory database always opens")
+    }
+
+#[doc = r#"Reloads `docs` from the file (and journal), discarding any changes made to `docs` directly.
+
+Writes already do this on their own when the file changed underneath them, see [`ConflictPolicy`].
+
+# Errors
+
+Same as [`DataBase::open`].
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_reload.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut reader: DataBase<Task> = DataBase::open(path).unwrap();
+    let mut writer: DataBase<Task> = DataBase::open(path).unwrap();
+    writer.push(Task::default()).await.unwrap();
 
-        Ok(Self {
-            file_path: path.to_string(),
-            docs
-        })
+    assert!(reader.changed_on_disk().unwrap());
+    reader.reload().unwrap();
+    assert_eq!(reader.docs.len(), 1);
+    assert!(!reader.changed_on_disk().unwrap());
+    # });
+}
+```"#]
+    pub fn reload(&mut self) -> io::Result<()> {
+        self.load_locked()
+    }
+
+#[doc = r#"Returns whether the file (or journal) was written by someone else since this database last read or wrote it.
+
+# Errors
+
+Function will throw an `io::error::Error` if the file's metadata can't be read.
+"#]
+    pub fn changed_on_disk(&self) -> io::Result<bool> {
+        Ok(self.fingerprint()? != self.seen)
     }
 
 #[doc = r#"Adds a data to the database.
 
+The file is replaced atomically (temp file, fsync, rename, directory fsync), so a crash leaves either the old or the new database on disk.
+The fsyncs can be relaxed with [`Config::durability`]. If another process changed the file since it was
+last read, [`Config::on_conflict`] decides whether `docs` is reloaded first.
+
 # Errors
 
 This function may throw an `error` due to a number of different reasons. Some of them are listed bellow:
     1. Function will throw an `io::error::Error` if there is any problem locating or opening the database's json file.
     2. Function will throw an `serde_json::error:Error` if there is any problem serializing or de-serializing the data in the file.
     3. Function will throw an `io:error:Error` is input `data.get_id()` already exists in the data_base.
+    4. Function will throw a [`MemoError::Locked`] if another process holds the database lock, see [`LockMode`].
+    5. Function will throw a [`MemoError::Conflict`] if the file changed underneath it with [`ConflictPolicy::Error`].
 # Examples
 ```
 use serde::Serialize;
@@ -120,104 +404,1780 @@ use memorable::DataBase;
 use memorable_macro_derive::MemoDoc;
 use memorable::MemoDoc;
 
-#[derive(MemoDoc, Serialize, Deserialize, Default)]
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
 struct Data {
     uuid: String,
     // other fields.
 }
 
 fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_push.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
     let data = Data::default();
-    let mut f = DataBase::open("./path.json").unwrap();
-    f.push(data).unwrap();
-    println!("{:#?}", f.datas);
+    let mut f = DataBase::open(path).unwrap();
+    f.push(data).await.unwrap();
+    println!("{:#?}", f.docs);
+    # assert_eq!(f.docs.len(), 1);
+    # });
 }
 ```"#]
     pub async fn push(&mut self, mut data: T) -> io::Result<()>{
-        match self.docs.get(data.get_id()) {
-            Some(_) => {
-                return Err(StdError::new(ErrorKind::AlreadyExists, "data already exists"));
-            },
-            None => {}
-        }
-        if data.get_id() == "" {
+        if data.get_id().is_empty() {
             data.set_id(&uuid::Uuid::new_v4().to_string());
         }
-        let mut file: File = File::options().truncate(false).read(true).write(true).open(&self.file_path)?;
-        let mut buff: String = String::new();
-        file.read_to_string(&mut buff)?;
-        let mut docs: HashMap<String, T> = serde_json::from_str(&buff).unwrap_or_else(|e| {
-            println!("Err: {}", e);
-            HashMap::<String, T>::new()
-        });
-        docs.insert(data.get_id().to_string(), data);
-        file.rewind()?;
-        file.write_all(serde_json::to_string_pretty(&docs)?.as_bytes())?;
-        self.docs = docs;
-        Ok(())
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        self.check_conflict()?;
+        if self.docs.contains_key(data.get_id()) || (self.is_lazy() && self.stored(data.get_id())?) {
+            return Err(StdError::new(ErrorKind::AlreadyExists, "data already exists"));
+        }
+        self.put_locked(data)
     }
 
 #[doc = r#"Deletes a data to the database.
 
+Like [`DataBase::push`], the file is replaced atomically and external changes are handled with [`Config::on_conflict`].
+If the write fails the document is kept in `docs`.
+
 # Errors
 
-Function will throw an `io::error::Error` if no data was found with specified id.
+Function will throw an `io::error::Error` if no data was found with specified id,
+a [`MemoError::Locked`] if another process holds the database lock, see [`LockMode`],
+or a [`MemoError::Conflict`] if the file changed underneath it with [`ConflictPolicy::Error`].
 # Examples
 ```
 use serde::Serialize;
 use serde::Deserialize;
-use memorable::DataBase;
-use memorable::memorable_macro_derive::MemoDoc;
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
 
-#[derive(MemoDoc, Serialize, Deserialize, Default)]
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
 struct Data {
     uuid: String,
     // other fields.
 }
 
 fn main() {
-    let data = Data::default();
-    let mut f = DataBase::open("./path.json");
-    f.push(data).unwrap();
-    let val = f.del(data.get_id()).unwrap();
+    # let path = std::env::temp_dir().join("memorable_doc_del.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut data = Data::default();
+    data.set_id("to-delete");
+    let mut f = DataBase::open(path).unwrap();
+    f.push(data.clone()).await.unwrap();
+    let val = f.del(data.get_id()).await.unwrap();
     println!("Deleted: {:#?}", val);
-    println!("Remaining: {:#?}", f.datas);
+    println!("Remaining: {:#?}", f.docs);
+    # assert!(f.docs.is_empty());
+    # assert!(DataBase::<Data>::open(path).unwrap().docs.is_empty());
+    # });
 }
 ```"#]
     pub async fn del(&mut self, id: &str) -> io::Result<T> {
-        match self.docs.remove(id) {
-            Some(v) => {
-                let mut file: File = File::options().truncate(true).write(true).open(&self.file_path)?;
-                let buff: String = serde_json::to_string_pretty(&self.docs)?;
-                file.rewind()?;
-                file.write_all(buff.as_bytes())?;
-                Ok(v)
-            },
-            None => Err(StdError::new(ErrorKind::NotFound, format!("Data with specified ID ({id}) was not found.")))
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        self.check_conflict()?;
+        let Some(previous) = self.current(id)? else {
+            return Err(StdError::new(ErrorKind::NotFound, format!("Data with specified ID ({id}) was not found.")));
+        };
+        self.commit(BTreeMap::from([(id.to_string(), None)]))?;
+        Ok(previous)
+    }
+
+#[doc = r#"Changes the document stored under `id` in place and returns its previous value.
+
+`f` is called on a copy of the document, which then replaces the stored one in a single write: one
+record in [`StorageMode::Journal`] and [`StorageMode::Lines`], one file in the per-document modes,
+the snapshot otherwise. The lock is held from reading the document to writing it, so no other
+process can change it in between. Like [`DataBase::push`], external changes are handled with
+[`Config::on_conflict`], and `docs` is left unchanged if the write fails.
+
+# Errors
+
+Function will throw an `io::error::Error` of kind `NotFound` if no data was found with specified id,
+of kind `InvalidInput` if `f` changed the id, or any error [`DataBase::push`] can throw.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Data {
+    uuid: String,
+    count: u32,
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_update.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut f = DataBase::open(path).unwrap();
+    f.push(Data { uuid: "counter".into(), count: 1 }).await.unwrap();
+    let previous = f.update("counter", |data| data.count += 1).await.unwrap();
+    assert_eq!(previous.count, 1);
+    assert_eq!(f.get("counter").await.unwrap().count, 2);
+    # assert_eq!(DataBase::<Data>::open(path).unwrap().docs["counter"].count, 2);
+    # });
+}
+```"#]
+    pub async fn update(&mut self, id: &str, f: impl FnOnce(&mut T)) -> io::Result<T> {
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        self.check_conflict()?;
+        let Some(previous) = self.current(id)? else {
+            return Err(StdError::new(ErrorKind::NotFound, format!("Data with specified ID ({id}) was not found.")));
+        };
+        let mut data: T = previous.clone();
+        f(&mut data);
+        if data.get_id() != id {
+            return Err(StdError::new(ErrorKind::InvalidInput, format!("update changed the ID ({id}) to ({})", data.get_id())));
         }
+        self.put_locked(data)?;
+        Ok(previous)
     }
 
-#[doc = r#"Fetches a data to the database.
+#[doc = r#"Replaces the document with the id of `data` and returns its previous value.
 
+The document is written like in [`DataBase::update`], in a single write.
+
+# Errors
+
+Function will throw an `io::error::Error` of kind `NotFound` if no data was found with the id of `data`,
+or any error [`DataBase::push`] can throw.
 # Examples
 ```
-use memorable::DataBase;
-use memorable::memorable_macro_derive::MemoDoc;c
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
 
-#[derive(MemoDoc, Serialize, Deserialize, Default)]
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
 struct Data {
     uuid: String,
-    // other fields.
+    name: String,
 }
 
 fn main() {
-    let data = Data::default();
-    let mut f = DataBase::open("./path.json");
-    f.push(data).unwrap();
-    println!("Requested: {:#?}", f.get(data.get_id()));
+    # let path = std::env::temp_dir().join("memorable_doc_replace.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut f = DataBase::open(path).unwrap();
+    f.push(Data { uuid: "user".into(), name: "Ray".into() }).await.unwrap();
+    let previous = f.replace(Data { uuid: "user".into(), name: "Rayray".into() }).await.unwrap();
+    assert_eq!(previous.name, "Ray");
+    assert!(f.replace(Data { uuid: "nobody".into(), name: "Nobody".into() }).await.is_err());
+    # assert_eq!(f.docs["user"].name, "Rayray");
+    # assert!(!f.docs.contains_key("nobody"));
+    # });
 }
 ```"#]
-    pub async fn get(&self, id: &str) -> Option<T> {
-        self.docs.get(id).cloned()
+    pub async fn replace(&mut self, data: T) -> io::Result<T> {
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        self.check_conflict()?;
+        let Some(previous) = self.current(data.get_id())? else {
+            return Err(StdError::new(ErrorKind::NotFound, format!("Data with specified ID ({}) was not found.", data.get_id())));
+        };
+        self.put_locked(data)?;
+        Ok(previous)
+    }
+
+#[doc = r#"Adds `data` to the database, or replaces the document with its id, and returns the previous value if there was one.
+
+Like [`DataBase::push`], an empty id is replaced by a random one. The document is written like in
+[`DataBase::update`], in a single write.
+
+# Errors
+
+Function will throw any error [`DataBase::push`] can throw, except for an already existing id.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Data {
+    uuid: String,
+    name: String,
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_upsert.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut f = DataBase::open(path).unwrap();
+    assert!(f.upsert(Data { uuid: "user".into(), name: "Ray".into() }).await.unwrap().is_none());
+    let previous = f.upsert(Data { uuid: "user".into(), name: "Rayray".into() }).await.unwrap();
+    assert_eq!(previous.unwrap().name, "Ray");
+    # assert_eq!(DataBase::<Data>::open(path).unwrap().docs["user"].name, "Rayray");
+    # });
+}
+```"#]
+    pub async fn upsert(&mut self, mut data: T) -> io::Result<Option<T>> {
+        if data.get_id().is_empty() {
+            data.set_id(&uuid::Uuid::new_v4().to_string());
+        }
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        self.check_conflict()?;
+        let previous: Option<T> = self.current(data.get_id())?;
+        self.put_locked(data)?;
+        Ok(previous)
+    }
+
+#[doc = r#"Applies `patch` to the document stored under `id` and returns its previous value, behind the `patch` cargo feature.
+
+The document is turned into a `serde_json::Value`, patched, and deserialized back into `T`, so a
+patch that leaves it malformed is rejected. It is then written like in [`DataBase::update`], in a
+single write.
+
+# Errors
+
+Function will throw an `io::error::Error` of kind `NotFound` if no data was found with specified id,
+of kind `InvalidInput` if the patch is malformed, fails, or changes the id, of kind `InvalidData`
+if the patched document isn't a valid `T`, or any error [`DataBase::push`] can throw.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use memorable::{DataBase, MemoDoc, Patch};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Data {
+    uuid: String,
+    name: String,
+    tags: Vec<String>,
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_patch.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut f = DataBase::open(path).unwrap();
+    f.push(Data { uuid: "user".into(), name: "Ray".into(), tags: vec![] }).await.unwrap();
+
+    f.patch("user", Patch::Merge(json!({ "name": "Rayray" }))).await.unwrap();
+    f.patch("user", Patch::Json(json!([{ "op": "add", "path": "/tags/-", "value": "admin" }]))).await.unwrap();
+    assert_eq!(f.docs["user"].name, "Rayray");
+    assert_eq!(f.docs["user"].tags, ["admin"]);
+
+    // Neither the id nor the shape of the document can be patched away.
+    assert!(f.patch("user", Patch::Merge(json!({ "uuid": "someone-else" }))).await.is_err());
+    assert!(f.patch("user", Patch::Merge(json!({ "tags": null }))).await.is_err());
+    # assert_eq!(DataBase::<Data>::open(path).unwrap().docs["user"].tags, ["admin"]);
+    # });
+}
+```"#]
+    #[cfg(feature = "patch")]
+    pub async fn patch(&mut self, id: &str, patch: Patch) -> io::Result<T> {
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        self.check_conflict()?;
+        let Some(previous) = self.current(id)? else {
+            return Err(StdError::new(ErrorKind::NotFound, format!("Data with specified ID ({id}) was not found.")));
+        };
+        let mut value: serde_json::Value = serde_json::to_value(&previous)?;
+        patch.apply(&mut value)?;
+        let data: T = serde_json::from_value(value)?;
+        if data.get_id() != id {
+            return Err(StdError::new(ErrorKind::InvalidInput, format!("patch changed the ID ({id}) to ({})", data.get_id())));
+        }
+        self.put_locked(data)?;
+        Ok(previous)
+    }
+
+#[doc = r#"Adds every document of `data` in a single write and returns the result of each, see [`DataBase::apply`].
+
+# Errors
+
+Function will throw any error [`DataBase::apply`] can throw. A document whose id already exists,
+in the database or earlier in `data`, fails on its own with an `io::error::Error` of kind `AlreadyExists`.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{BatchMode, DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Data {
+    uuid: String,
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_push_many.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut f = DataBase::open(path).unwrap();
+    let data: Vec<Data> = (0..10_000).map(|i| Data { uuid: format!("record-{i}") }).collect();
+    let results = f.push_many(data, BatchMode::AllOrNothing).await.unwrap();
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert_eq!(f.docs.len(), 10_000);
+    # assert_eq!(DataBase::<Data>::open(path).unwrap().docs.len(), 10_000);
+    # });
+}
+```"#]
+    pub async fn push_many(&mut self, data: Vec<T>, mode: BatchMode) -> io::Result<Vec<io::Result<()>>> {
+        let results: Vec<io::Result<Option<T>>> = self.apply(data.into_iter().map(Op::Push).collect(), mode).await?;
+        Ok(results.into_iter().map(|r| r.map(|_| ())).collect())
+    }
+
+#[doc = r#"Deletes the document of every id in `ids` in a single write and returns the result of each, see [`DataBase::apply`].
+
+# Errors
+
+Function will throw any error [`DataBase::apply`] can throw. An id that doesn't exist, or was
+already deleted earlier in `ids`, fails on its own with an `io::error::Error` of kind `NotFound`.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{BatchMode, DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Data {
+    uuid: String,
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_del_many.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut f = DataBase::open(path).unwrap();
+    f.push_many(vec![Data { uuid: "a".into() }, Data { uuid: "b".into() }], BatchMode::Partial).await.unwrap();
+
+    // All or nothing: the missing id keeps "a" from being deleted.
+    let results = f.del_many(["a", "missing"], BatchMode::AllOrNothing).await.unwrap();
+    assert!(results.iter().all(|r| r.is_err()));
+    assert_eq!(f.docs.len(), 2);
+
+    // Partial: "a" is deleted, the missing id is reported on its own.
+    let results = f.del_many(["a", "missing"], BatchMode::Partial).await.unwrap();
+    assert_eq!(results[0].as_ref().unwrap().uuid, "a");
+    assert_eq!(results[1].as_ref().unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    assert_eq!(f.docs.len(), 1);
+    # });
+}
+```"#]
+    pub async fn del_many(&mut self, ids: impl IntoIterator<Item = impl Into<String>>, mode: BatchMode) -> io::Result<Vec<io::Result<T>>> {
+        let results: Vec<io::Result<Option<T>>> = self.apply(ids.into_iter().map(|id| Op::Del(id.into())).collect(), mode).await?;
+        Ok(results.into_iter().map(|r| r.map(|previous| previous.expect("a successful delete returns the document"))).collect())
+    }
+
+#[doc = r#"Applies every operation of `ops` in order, in a single write, and returns the result of each:
+the previous value of the document on success.
+
+Each operation is checked against the database as the operations before it in `ops` left it, so a
+document can be pushed and then replaced or deleted within one batch. With [`BatchMode::Partial`]
+the operations that fail are skipped, with [`BatchMode::AllOrNothing`] nothing is written if any
+fails. The valid operations are then written at once: as one append in [`StorageMode::Journal`]
+and [`StorageMode::Lines`], which a crash leaves either whole or not at all, or as one snapshot.
+[`StorageMode::Directory`] and [`StorageMode::Bitcask`] write one key per document, and put the
+written ones back if a later one fails, but a crash halfway can leave part of the batch.
+
+# Errors
+
+The returned `Vec` carries the errors of single operations: an `io::error::Error` of kind
+`AlreadyExists` or `NotFound` like [`DataBase::push`] and [`DataBase::del`], or a
+[`MemoError::Aborted`] for the valid operations of a failed [`BatchMode::AllOrNothing`] batch.
+The function itself will throw an `io::error::Error` if the write fails, in which case none of the
+operations were applied, or any other error [`DataBase::push`] can throw.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{BatchMode, DataBase, MemoDoc, Op};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Data {
+    uuid: String,
+    name: String,
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_apply.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut f = DataBase::open(path).unwrap();
+    f.push(Data { uuid: "old".into(), name: "Old".into() }).await.unwrap();
+    let results = f.apply(vec![
+        Op::Push(Data { uuid: "new".into(), name: "New".into() }),
+        Op::Replace(Data { uuid: "new".into(), name: "Newer".into() }),
+        Op::Del("old".into()),
+        Op::Del("old".into()),
+    ], BatchMode::Partial).await.unwrap();
+    assert!(results[0].as_ref().unwrap().is_none());
+    assert_eq!(results[1].as_ref().unwrap().as_ref().unwrap().name, "New");
+    assert_eq!(results[2].as_ref().unwrap().as_ref().unwrap().name, "Old");
+    assert!(results[3].is_err());
+    assert_eq!(f.docs["new"].name, "Newer");
+    assert!(!f.docs.contains_key("old"));
+    # let f: DataBase<Data> = DataBase::open(path).unwrap();
+    # assert_eq!(f.docs.len(), 1);
+    # });
+}
+```"#]
+    pub async fn apply(&mut self, ops: Vec<Op<T>>, mode: BatchMode) -> io::Result<Vec<io::Result<Option<T>>>> {
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        self.check_conflict()?;
+        // Every document the batch changed as it stands after the operations so far, `None` if deleted.
+        let mut changes: BTreeMap<String, Option<T>> = BTreeMap::new();
+        let mut results: Vec<io::Result<Option<T>>> = Vec::with_capacity(ops.len());
+        for op in ops {
+            results.push(self.stage(&mut changes, op));
+        }
+        if let (BatchMode::AllOrNothing, Some(index)) = (mode, results.iter().position(|r| r.is_err())) {
+            return Ok(results.into_iter().map(|r| r.and(Err(MemoError::Aborted { index }.into()))).collect());
+        }
+        if !changes.is_empty() {
+            self.commit(changes)?;
+        }
+        Ok(results)
+    }
+
+#[doc = r#"Runs `f` as a transaction: its changes are committed together if it returns `Ok`, and dropped otherwise.
+
+Reads through the [`Transaction`] see its own changes, while nothing reaches the storage or `docs`
+before the commit. If `f` returns an error or panics, the database is left exactly as it was. The
+exclusive lock is held from the start of `f` to the end of the commit, and the commit is a single
+write like in [`DataBase::apply`], with the same guarantees under a crash.
+
+# Errors
+
+Function will throw the error `f` returns, an `io::error::Error` if the commit fails, in which case
+nothing was applied, or any error [`DataBase::push`] can throw.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+use std::{io, panic::{self, AssertUnwindSafe}};
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Account {
+    uuid: String,
+    balance: i64,
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_transaction.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut f = DataBase::open(path).unwrap();
+    f.push(Account { uuid: "alice".into(), balance: 100 }).await.unwrap();
+
+    f.transaction(|tx| {
+        tx.push(Account { uuid: "bob".into(), balance: 0 })?;
+        tx.update("alice", |a| a.balance -= 30)?;
+        tx.update("bob", |b| b.balance += 30)?;
+        assert_eq!(tx.get("bob").unwrap().balance, 30);
+        Ok(())
+    }).await.unwrap();
+    assert_eq!(f.docs["alice"].balance, 70);
+
+    // An error rolls the transfer back.
+    let overdrawn: io::Result<()> = f.transaction(|tx| {
+        let alice = tx.update("alice", |a| a.balance -= 100)?;
+        tx.update("bob", |b| b.balance += 100)?;
+        match alice.balance < 100 {
+            true => Err(io::Error::other("insufficient funds")),
+            false => Ok(()),
+        }
+    }).await;
+    assert!(overdrawn.is_err());
+    assert_eq!(f.docs["alice"].balance, 70);
+
+    // So does a panic.
+    # let hook = panic::take_hook();
+    # panic::set_hook(Box::new(|_| {}));
+    let panicked = panic::catch_unwind(AssertUnwindSafe(|| pollster::block_on(f.transaction(|tx| -> io::Result<()> {
+        tx.del("alice")?;
+        panic!("bug in the middle of a transaction");
+    }))));
+    # panic::set_hook(hook);
+    assert!(panicked.is_err());
+    assert!(f.docs.contains_key("alice"));
+    # assert_eq!(DataBase::<Account>::open(path).unwrap().docs["bob"].balance, 30);
+    # });
+}
+```"#]
+    pub async fn transaction<R>(&mut self, f: impl FnOnce(&mut Transaction<'_, T>) -> io::Result<R>) -> io::Result<R> {
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        self.check_conflict()?;
+        let mut tx: Transaction<'_, T> = Transaction::new(self);
+        let out: R = f(&mut tx)?;
+        let changes: BTreeMap<String, Option<T>> = tx.into_changes();
+        if !changes.is_empty() {
+            self.commit(changes)?;
+        }
+        Ok(out)
+    }
+
+#[doc = r#"Fetches a data to the database.
+
+A lazy database that fails to read the document from storage returns `None`, see
+[`DataBase::try_get`] to tell that apart from a missing document.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Data {
+    uuid: String,
+    // other fields.
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_get.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut data = Data::default();
+    data.set_id("requested");
+    let mut f = DataBase::open(path).unwrap();
+    f.push(data.clone()).await.unwrap();
+    println!("Requested: {:#?}", f.get(data.get_id()).await);
+    # assert!(f.get("requested").await.is_some());
+    # });
+}
+```"#]
+    pub async fn get(&self, id: &str) -> Option<T> {
+        self.try_get(id).await.ok().flatten()
+    }
+
+#[doc = r#"Fetches a data to the database, or the error of reading it from the storage of a lazy database.
+
+# Errors
+
+With [`Config::lazy`], function will throw an `io::error::Error` if the document can't be read, a
+[`MemoError::Corrupted`] if it can't be de-serialized, or a [`MemoError::Conflict`] if another process
+rewrote the lines file since this database indexed it. Without it nothing is read and this never fails.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, MemoDoc, StorageMode};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let dir = std::env::temp_dir().join("memorable_doc_try_get");
+    # let _ = std::fs::remove_dir_all(&dir);
+    # pollster::block_on(async {
+    let config = Config { mode: StorageMode::Directory, lazy: true, ..Config::default() };
+    let mut f: DataBase<Task> = DataBase::open_with(dir.to_str().unwrap(), config.clone()).unwrap();
+    f.push(Task { uuid: "a".into() }).await.unwrap();
+    assert!(f.try_get("b").await.unwrap().is_none());
+
+    std::fs::write(dir.join("a.json"), "not json").unwrap();
+    let f: DataBase<Task> = DataBase::open_with(dir.to_str().unwrap(), config).unwrap();
+    assert!(f.try_get("a").await.is_err());
+    assert!(f.get("a").await.is_none());
+    # });
+}
+```"#]
+    pub async fn try_get(&self, id: &str) -> io::Result<Option<T>> {
+        match self.docs.get(id) {
+            Some(doc) => Ok(Some(doc.clone())),
+            None if self.is_lazy() => {
+                if let Some(doc) = self.cache.get(id) {
+                    return Ok(Some(doc));
+                }
+                let doc: Option<T> = self.read_doc(id)?;
+                if let Some(doc) = &doc {
+                    self.cache.insert(id, doc.clone());
+                }
+                Ok(doc)
+            },
+            None => Ok(None),
+        }
+    }
+
+#[doc = r#"Returns the ids of all documents, sorted.
+
+These are the keys of `docs`, except with [`Config::lazy`] where the storage or the index of the
+lines file is listed to include the documents that weren't read.
+
+# Errors
+
+Function will throw an `io::error::Error` if the storage of a lazy database can't be listed.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, MemoDoc, StorageMode};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let dir = std::env::temp_dir().join("memorable_doc_ids");
+    # let dir = dir.to_str().unwrap();
+    # let _ = std::fs::remove_dir_all(dir);
+    # pollster::block_on(async {
+    let config = Config { mode: StorageMode::Directory, lazy: true, ..Config::default() };
+    let mut f: DataBase<Task> = DataBase::open_with(dir, config.clone()).unwrap();
+    f.push(Task { uuid: "b".into() }).await.unwrap();
+    f.push(Task { uuid: "a".into() }).await.unwrap();
+
+    let mut f: DataBase<Task> = DataBase::open_with(dir, config).unwrap();
+    assert!(f.docs.is_empty());
+    assert_eq!(f.ids().unwrap(), ["a", "b"]);
+    assert_eq!(f.get("b").await.unwrap().uuid, "b");
+    f.del("a").await.unwrap();
+    assert_eq!(f.ids().unwrap(), ["b"]);
+    # });
+}
+```"#]
+    pub fn ids(&self) -> io::Result<Vec<String>> {
+        if self.is_lazy() && self.per_document() {
+            return self.storage.list();
+        }
+        let mut ids: Vec<String> = match self.is_lazy() {
+            true => self.offsets.keys().cloned().collect(),
+            false => self.docs.keys().cloned().collect(),
+        };
+        ids.sort();
+        Ok(ids)
+    }
+
+#[doc = r#"Rewrites the storage to hold only the live documents and reports how many bytes that reclaimed.
+
+With [`StorageMode::Journal`] the journal is folded into the snapshot file and removed, with
+[`StorageMode::Lines`] the file is rewritten without tombstones and overwritten lines, and with
+[`StorageMode::Bitcask`] every segment is merged into one. [`StorageMode::Snapshot`] and
+[`StorageMode::Directory`] keep no garbage, so nothing is done and nothing reclaimed.
+
+Folding runs automatically whenever the journal grows past [`Config::journal_limit`], and the
+other modes compact as [`Config::auto_compact`] asks for.
+
+Compaction is crash-safe. A lines file is replaced atomically like a snapshot. The snapshot is
+replaced atomically before the journal is dropped, and replaying a journal twice is harmless, so
+a crash in between loses nothing. Merged bitcask segments are written and synced under a temp
+name, renamed into place and only then are the old segments removed; records found in both are
+the same, so reading both after a crash is harmless too.
+
+# Errors
+
+Function will throw an `io::error::Error` if the snapshot can't be written or the journal can't be removed,
+or any error [`DataBase::push`] may throw while taking the lock.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, MemoDoc, StorageMode};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_compact.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let config = Config { mode: StorageMode::Journal, ..Config::default() };
+    let mut f: DataBase<Task> = DataBase::open_with(path, config).unwrap();
+    f.push(Task::default()).await.unwrap();
+    let compaction = f.compact().unwrap();
+    assert!(compaction.reclaimed() > 0);
+
+    assert!(!std::path::Path::new(&format!("{path}.journal")).exists());
+    assert_eq!(DataBase::<Task>::open(path).unwrap().docs.len(), 1);
+    # });
+}
+```"#]
+    pub fn compact(&mut self) -> io::Result<Compaction> {
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        self.check_conflict()?;
+        self.compact_locked()
+    }
+
+    /// [`DataBase::compact`] for a caller that holds the exclusive lock.
+    fn compact_locked(&mut self) -> io::Result<Compaction> {
+        let before: u64 = self.storage.usage()?.total;
+        match self.config.mode {
+            StorageMode::Snapshot | StorageMode::Directory => {},
+            StorageMode::Journal => self.fold_journal()?,
+            StorageMode::Lines => self.write_snapshot()?,
+            StorageMode::Bitcask => {
+                self.storage.compact()?;
+                self.seen = self.fingerprint()?;
+            },
+        }
+        Ok(Compaction { before, after: self.storage.usage()?.total })
+    }
+
+    /// Compacts as [`Config::auto_compact`] asks for after a write. The caller holds the exclusive lock.
+    fn compact_if_due(&mut self) {
+        let usage: io::Result<Usage> = match self.config.mode {
+            StorageMode::Lines => Ok(Usage { total: self.journal_len, live: self.live_len }),
+            StorageMode::Bitcask => self.storage.usage(),
+            _ => return,
+        };
+        // The write is durable at this point, a failed compaction is simply retried on the next one.
+        let compacted: io::Result<()> = usage.and_then(|usage| match self.config.auto_compact.due(usage) {
+            true => self.compact_locked().map(|_| ()),
+            false => Ok(()),
+        });
+        if let Err(e) = compacted {
+            self.defer(e);
+        }
+    }
+
+    /// Writes `docs` as the new snapshot and drops the journal. The caller holds the exclusive lock.
+    fn fold_journal(&mut self) -> io::Result<()> {
+        self.write_snapshot()?;
+        // The journal's removal must be as durable as the snapshot it was folded into.
+        let level: SyncLevel = self.sync_level();
+        self.storage.remove(journal::KEY, level)?;
+        self.synced(level);
+        self.journal_len = 0;
+        self.torn = false;
+        self.seen = self.fingerprint()?;
+        Ok(())
+    }
+
+#[doc = r#"Forces the database file, its journal and their directory to the disk.
+
+Only needed with [`Durability::None`], [`Durability::Flush`] or [`Durability::FsyncInterval`],
+e.g. before shutting down, as [`Durability::Fsync`] already syncs every write.
+
+# Errors
+
+Function will throw an `io::error::Error` if the database file can't be opened or synced.
+"#]
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.storage.sync()?;
+        self.last_sync = Some(Instant::now());
+        Ok(())
+    }
+
+#[doc = r#"Returns when a write was last fully synced to the disk, or `None` if it hasn't happened since `open`.
+
+See [`Durability`] for which writes are synced."#]
+    pub fn last_sync(&self) -> Option<Instant> {
+        self.last_sync
+    }
+
+#[doc = r#"Writes a point-in-time copy of the database to `dest`.
+
+The copy is taken under the exclusive lock after applying [`Config::on_conflict`], so it matches
+what the file holds at that moment, with any journal already folded in. `dest` is a plain
+database file that can be opened directly or brought back with [`DataBase::restore`]. It is
+written atomically and fsynced regardless of [`Config::durability`]. The file is a single encoded
+map, so a [`Config::lazy`] database reads every document into memory to write it.
+
+# Errors
+
+Function will throw an `io::error::Error` if `dest` can't be written, or any error [`DataBase::push`] may throw while taking the lock.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let dir = std::env::temp_dir().join("memorable_doc_snapshot");
+    # let _ = std::fs::remove_dir_all(&dir);
+    # std::fs::create_dir_all(&dir).unwrap();
+    # let path = dir.join("db.json");
+    # let path = path.to_str().unwrap();
+    # let snapshot = dir.join("db.snapshot.json");
+    # let snapshot = snapshot.to_str().unwrap();
+    # pollster::block_on(async {
+    let mut f: DataBase<Task> = DataBase::open(path).unwrap();
+    f.push(Task::default()).await.unwrap();
+    f.snapshot(snapshot).unwrap();
+
+    f.push(Task::default()).await.unwrap();
+    f.restore(snapshot).unwrap();
+    assert_eq!(f.docs.len(), 1);
+    assert_eq!(DataBase::<Task>::open(path).unwrap().docs.len(), 1);
+    # });
+}
+```"#]
+    pub fn snapshot(&mut self, dest: &str) -> io::Result<()> {
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        self.check_conflict()?;
+        let unread: HashMap<String, T>;
+        let docs: &HashMap<String, T> = if self.is_lazy() {
+            unread = self.read_all()?;
+            &unread
+        } else {
+            &self.docs
+        };
+        let buff: Vec<u8> = self.encode_file(docs, self.next_header().as_ref())?;
+        disk::write_atomic(Path::new(dest), &buff, SyncLevel::All)
+    }
+
+#[doc = r#"Replaces the database with the snapshot at `src`, as written by [`DataBase::snapshot`].
+
+The snapshot is validated before anything is touched: it must parse as a database of `T` (after
+[`Config::migrations`] upgraded it, if it is of an older schema version) and every document must
+be stored under its own `get_id()`. In [`StorageMode::Snapshot`], [`StorageMode::Journal`] and
+[`StorageMode::Lines`] the replacement is atomic; in journal mode the journal is folded into the
+old snapshot first, so a crash leaves either the old or the restored state.
+
+[`StorageMode::Directory`] and [`StorageMode::Bitcask`] write and remove one document at a time,
+so there the restore is not atomic: a crash or failed write halfway leaves a mix of old and
+restored documents on disk, while `docs` is put back to the old ones. Restoring again finishes the job.
+
+# Errors
+
+Function will throw an `io::error::Error` if `src` can't be read, a [`MemoError::Corrupted`] naming `src` if it
+fails validation, or any error [`DataBase::push`] may throw while writing.
+"#]
+    pub fn restore(&mut self, src: &str) -> io::Result<()> {
+        let corrupted = |reason: String| MemoError::Corrupted { path: PathBuf::from(src), reason };
+        let buff: Vec<u8> = self.decode_file(fs::read(src)?, Path::new(src)).map_err(|e| match e.kind() {
+            ErrorKind::InvalidData => corrupted(e.to_string()).into(),
+            _ => e,
+        })?;
+        let mut parsed: format::Parsed = format::parse(&buff, self.config.codec.as_ref()).map_err(|e| corrupted(e.to_string()))?;
+        if let Some(problem) = parsed.problems.first() {
+            return Err(corrupted(problem.to_string()).into());
+        }
+        self.migrate(&mut parsed, Path::new(src))?;
+        let docs: HashMap<String, T> = parsed.into_docs().map_err(|e| corrupted(e.to_string()))?;
+        if let Some((id, _)) = docs.iter().find(|(id, doc)| doc.get_id() != id.as_str()) {
+            return Err(corrupted(format!("document stored under {id} has a different id")).into());
+        }
+
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        if self.config.mode == StorageMode::Journal {
+            self.fold_journal()?;
+        }
+        let previous: HashMap<String, T> = std::mem::replace(&mut self.docs, docs);
+        let offsets: journal::Offsets = std::mem::take(&mut self.offsets);
+        if let Err(e) = self.write_snapshot() {
+            self.docs = previous;
+            self.offsets = offsets;
+            return Err(e);
+        }
+        if self.is_lazy() {
+            self.docs.clear();
+        }
+        self.cache.clear();
+        Ok(())
+    }
+
+#[doc = r#"Snapshots the database into `dir` and keeps only the `keep` most recent snapshots there.
+
+Snapshots are named `<file name>.<unix millis>.snapshot`; other files in `dir` are left alone.
+Returns the path of the new snapshot.
+
+# Errors
+
+Same as [`DataBase::snapshot`], plus any `io::error::Error` from listing or removing old snapshots in `dir`,
+or one of kind `InvalidInput` if `keep` is 0, as the new snapshot must be kept.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # let dir = std::env::temp_dir().join("memorable_doc_backup");
+    # let _ = std::fs::remove_dir_all(&dir);
+    # std::fs::create_dir_all(dir.join("backups")).unwrap();
+    # let path = dir.join("db.json");
+    # let path = path.to_str().unwrap();
+    # let backups = dir.join("backups");
+    # let backups = backups.to_str().unwrap();
+    # pollster::block_on(async {
+    let mut f: DataBase<Task> = DataBase::open(path).unwrap();
+    for _ in 0..3 {
+        f.push(Task::default()).await.unwrap();
+        f.backup(backups, 2).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+    }
+    assert_eq!(std::fs::read_dir(backups).unwrap().count(), 2);
+    assert!(f.backup(backups, 0).is_err());
+    # });
+}
+```"#]
+    pub fn backup(&mut self, dir: &str, keep: usize) -> io::Result<PathBuf> {
+        if keep == 0 {
+            return Err(StdError::new(ErrorKind::InvalidInput, "backup must keep at least the snapshot it takes"));
+        }
+        let name: String = disk::file_name(&self.storage.locate(""));
+        let dest: PathBuf = disk::timestamped(&Path::new(dir).join(&name), "snapshot");
+        self.snapshot(&dest.to_string_lossy())?;
+        disk::prune(Path::new(dir), &name, "snapshot", keep)?;
+        Ok(dest)
+    }
+
+#[doc = r#"Checks the file (and journal) on disk and reports everything that is wrong with it.
+
+Every document is de-serialized and checked against the key it is stored under. For files
+written with [`Config::envelope`] the format version, document count and checksum are checked
+too, which catches truncation and bit rot that still leaves valid json. That is every file
+written by default. An empty list means the
+file is intact; a missing file has nothing wrong with it.
+
+# Errors
+
+Function will throw an `io::error::Error` if the file can't be read, or a [`MemoError::Locked`] if the shared lock can't be taken.
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, IntegrityError, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String,
+    done: bool
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_verify.json");
+    # let path = path.to_str().unwrap();
+    # let _ = std::fs::remove_file(path);
+    # pollster::block_on(async {
+    let mut f: DataBase<Task> = DataBase::open(path).unwrap();
+    f.push(Task::default()).await.unwrap();
+    assert!(f.verify().unwrap().is_empty());
+
+    // Flip a field without touching the checksum.
+    let rotten = std::fs::read_to_string(path).unwrap().replace("false", "true");
+    std::fs::write(path, rotten).unwrap();
+    assert!(matches!(f.verify().unwrap()[..], [IntegrityError::ChecksumMismatch { .. }]));
+    # });
+}
+```"#]
+    pub fn verify(&self) -> io::Result<Vec<IntegrityError>> {
+        let _lock: Option<LockGuard> = self.lock(false)?;
+        let mut problems: Vec<IntegrityError> = Vec::new();
+        if self.per_document() {
+            for key in self.storage.list()? {
+                let buff: Vec<u8> = match self.storage.read(&key) {
+                    Ok(Some(buff)) => buff,
+                    Ok(None) => continue,
+                    Err(e) if e.kind() == ErrorKind::InvalidData => {
+                        problems.push(IntegrityError::InvalidDocument { id: key, reason: e.to_string() });
+                        continue;
+                    },
+                    Err(e) => return Err(e),
+                };
+                match serde_json::from_slice::<T>(&buff) {
+                    Ok(doc) if doc.get_id() != key => problems.push(IntegrityError::IdMismatch { id: doc.get_id().to_string(), key }),
+                    Ok(_) => {},
+                    Err(e) => problems.push(IntegrityError::InvalidDocument { id: key, reason: e.to_string() }),
+                }
+            }
+            return Ok(problems);
+        }
+        match self.storage.read("") {
+            // The file is the log itself, checked below.
+            _ if self.config.mode == StorageMode::Lines => {},
+            Ok(Some(buff)) => match self.decode_file(buff, &self.storage.locate("")) {
+                Ok(buff) => {
+                    let mut mismatched: Vec<IntegrityError> = Vec::new();
+                    (_, problems) = format::check(&buff, self.config.codec.as_ref(), |key, doc: T| {
+                        if doc.get_id() != key {
+                            mismatched.push(IntegrityError::IdMismatch { key: key.to_string(), id: doc.get_id().to_string() });
+                        }
+                    });
+                    problems.append(&mut mismatched);
+                },
+                Err(e) if e.kind() == ErrorKind::InvalidData => problems.push(IntegrityError::Unreadable { reason: e.to_string() }),
+                Err(e) => return Err(e),
+            },
+            Ok(None) => {},
+            Err(e) => return Err(e),
+        }
+
+        if let Some(log) = self.log_key() {
+            let buff: Vec<u8> = self.storage.read(log)?.unwrap_or_default();
+            for (offset, line) in journal::lines(&buff) {
+                match serde_json::from_slice::<journal::Record<T>>(line) {
+                    Ok(journal::Record::Put { id, doc }) if doc.get_id() != id => {
+                        problems.push(IntegrityError::IdMismatch { key: id, id: doc.get_id().to_string() });
+                    },
+                    Ok(_) => {},
+                    Err(e) => problems.push(IntegrityError::InvalidRecord { offset, reason: e.to_string() }),
+                }
+            }
+        }
+        Ok(problems)
+    }
+
+#[doc = r#"Returns the header of the file as it was last read or written, `None` for plain files without an envelope.
+
+Files written with [`Config::envelope`] turned off, or by versions before it, carry no header, and
+neither do the modes that don't store the documents in one file. See [`Header`] for an example."#]
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+#[doc = r#"Returns the `.corrupt` backups [`CorruptionPolicy::Backup`] made while opening or writing this database."#]
+    pub fn recovered(&self) -> &[PathBuf] {
+        &self.recovered
+    }
+
+#[doc = r#"Returns and clears the errors of work that follows a successful write without being part of it.
+
+An automatic compaction (see [`Config::auto_compact`]) or a fold of the journal (see
+[`Config::journal_limit`]) that fails doesn't fail the write that triggered it, since that write is
+already durable; it is simply retried by the next one. Neither does failing to put documents back
+after a batch failed halfway in a per-document mode, which is reported by the batch's own error.
+The most recent of these errors are kept here instead, at most 16 of them.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{DataBase, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # pollster::block_on(async {
+    let mut f: DataBase<Task> = DataBase::in_memory();
+    f.push(Task::default()).await.unwrap();
+    for e in f.take_errors() {
+        eprintln!("background work failed: {e}");
+    }
+    # });
+}
+```"#]
+    pub fn take_errors(&mut self) -> Vec<io::Error> {
+        self.deferred.drain(..).map(|(kind, message)| StdError::new(kind, message)).collect()
+    }
+
+    /// Keeps `err` for [`DataBase::take_errors`], dropping the oldest beyond the limit.
+    fn defer(&mut self, err: StdError) {
+        if self.deferred.len() >= DEFERRED {
+            self.deferred.remove(0);
+        }
+        self.deferred.push((err.kind(), err.to_string()));
+    }
+
+    /// Loads under a shared lock, retrying under the exclusive lock if the file must be written first.
+    fn load_locked(&mut self) -> io::Result<()> {
+        {
+            let _lock: Option<LockGuard> = self.lock(false)?;
+            if self.load(false)? {
+                return Ok(());
+            }
+        }
+        // Creating, recovering or compacting the file needs the exclusive lock.
+        let _lock: Option<LockGuard> = self.lock(true)?;
+        self.load(true)?;
+        Ok(())
+    }
+
+    fn fingerprint(&self) -> io::Result<[Option<u64>; 2]> {
+        let journal: Option<u64> = match self.config.mode {
+            StorageMode::Snapshot | StorageMode::Lines | StorageMode::Directory | StorageMode::Bitcask => None,
+            StorageMode::Journal => self.storage.fingerprint(journal::KEY)?,
+        };
+        Ok([self.storage.fingerprint("")?, journal])
+    }
+
+    /// Applies [`ConflictPolicy`] if the files changed since they were last seen. The caller holds the exclusive lock.
+    fn check_conflict(&mut self) -> io::Result<()> {
+        if !self.changed_on_disk()? {
+            return Ok(());
+        }
+        match self.config.on_conflict {
+            ConflictPolicy::Reload => self.load(true).map(|_| ()),
+            ConflictPolicy::Error => Err(MemoError::Conflict { path: self.storage.locate("") }.into()),
+            // Other writers' records would otherwise be replayed on top of ours.
+            ConflictPolicy::Overwrite => match self.config.mode {
+                // Every write only replaces its own document's file.
+                StorageMode::Snapshot | StorageMode::Directory | StorageMode::Bitcask => Ok(()),
+                StorageMode::Journal => self.fold_journal(),
+                StorageMode::Lines => self.write_snapshot(),
+            },
+        }
+    }
+
+    fn lock(&self, exclusive: bool) -> io::Result<Option<LockGuard>> {
+        self.storage.lock(exclusive, self.config.locking)
+    }
+
+    /// Loads the snapshot and replays the journal into `docs`.
+    ///
+    /// Under a shared lock (`writable == false`) nothing is written; if the file has to be created,
+    /// recovered or compacted this returns `Ok(false)` so the caller can retry with the exclusive lock.
+    fn load(&mut self, writable: bool) -> io::Result<bool> {
+        self.journal_len = 0;
+        self.torn = false;
+        self.cache.clear();
+        match self.config.mode {
+            StorageMode::Lines => return self.load_lines(writable),
+            StorageMode::Directory | StorageMode::Bitcask => return self.load_dir(writable),
+            _ => {},
+        }
+        let migrated_from: Option<u32> = match self.read_snapshot(writable)? {
+            Some(loaded) => {
+                if loaded.migrated_from.is_some() && !writable {
+                    return Ok(false);
+                }
+                self.docs = loaded.docs;
+                self.header = loaded.header;
+                loaded.migrated_from
+            },
+            None if !writable => return Ok(false),
+            None => {
+                self.docs = HashMap::new();
+                self.header = None;
+                self.write_snapshot()?;
+                None
+            },
+        };
+        if let Some(from) = migrated_from {
+            self.backup_before_migration(from)?;
+        }
+
+        if self.config.mode == StorageMode::Journal {
+            let (migrations, target) = (&self.config.migrations, self.config.schema_version);
+            let upgrade = |doc: &mut serde_json::Value| migrations.upgrade(migrated_from.unwrap_or(target), target, doc);
+            let upgrade: Option<journal::Upgrade> = migrated_from.map(|_| &upgrade as _);
+            match journal::replay(self.storage.as_ref(), journal::KEY, &mut self.docs, upgrade, None) {
+                Ok((len, torn)) => (self.journal_len, self.torn) = (len, torn),
+                Err(e) if !writable && self.recoverable(&e) => return Ok(false),
+                Err(e) => {
+                    // The records before the broken one were replayed, keep them before the journal is gone.
+                    self.recover(e, journal::KEY)?;
+                    self.write_snapshot()?;
+                },
+            }
+        }
+        if migrated_from.is_some() || self.journal_len > self.config.journal_limit {
+            if !writable {
+                return Ok(false);
+            }
+            match self.config.mode {
+                StorageMode::Journal => self.fold_journal()?,
+                _ => self.write_snapshot()?,
+            }
+        }
+        self.seen = self.fingerprint()?;
+        Ok(true)
+    }
+
+    /// [`DataBase::load`] for [`StorageMode::Directory`], reading every document file unless [`Config::lazy`].
+    fn load_dir(&mut self, writable: bool) -> io::Result<bool> {
+        self.docs = HashMap::new();
+        self.header = None;
+        // Listing a bitcask store reads its segments, so that is where their damage shows.
+        let ids: Vec<String> = loop {
+            let listed: io::Result<Vec<String>> = match self.config.lazy && self.config.mode == StorageMode::Directory {
+                true => Ok(Vec::new()),
+                false => self.storage.list(),
+            };
+            match listed {
+                Ok(ids) => break ids,
+                Err(e) if !writable && self.recoverable(&e) => return Ok(false),
+                Err(e) => self.recover(e, "")?,
+            }
+        };
+        if !self.config.lazy {
+            for id in ids {
+                match self.read_doc(&id) {
+                    Ok(Some(doc)) => {
+                        self.docs.insert(id, doc);
+                    },
+                    Ok(None) => {},
+                    Err(e) if !writable && self.recoverable(&e) => return Ok(false),
+                    Err(e) => self.recover(e, &id)?,
+                }
+            }
+        }
+        self.seen = self.fingerprint()?;
+        Ok(true)
+    }
+
+    /// [`DataBase::load`] for [`StorageMode::Lines`], replaying the file itself into `docs`.
+    fn load_lines(&mut self, writable: bool) -> io::Result<bool> {
+        let exists: bool = self.storage.fingerprint("")?.is_some();
+        if !exists && !writable {
+            return Ok(false);
+        }
+        self.docs = HashMap::new();
+        self.offsets = HashMap::new();
+        self.header = None;
+        let replayed: io::Result<(u64, bool)> = match self.is_lazy() {
+            true => journal::index(self.storage.as_ref(), "", &mut self.offsets),
+            false => journal::replay(self.storage.as_ref(), "", &mut self.docs, None, Some(&mut self.offsets)),
+        };
+        match replayed {
+            Ok((len, torn)) => (self.journal_len, self.torn) = (len, torn),
+            Err(e) if !writable && self.recoverable(&e) => return Ok(false),
+            Err(e) => {
+                // Keep the documents replayed before the broken line.
+                if self.is_lazy() && self.recoverable(&e) {
+                    self.docs = self.read_all()?;
+                    self.offsets = HashMap::new();
+                }
+                self.recover(e, "")?;
+                self.write_snapshot()?;
+                // The rewritten file is indexed, a lazy database reads the documents from it again.
+                if self.is_lazy() {
+                    self.docs.clear();
+                }
+            },
+        }
+        self.live_len = self.offsets.values().map(|(_, len)| *len as u64 + 1).sum();
+        if !exists {
+            self.write_snapshot()?;
+        }
+        self.seen = self.fingerprint()?;
+        Ok(true)
+    }
+
+    /// Reads and parses the snapshot file, upgrading documents of an older schema version.
+    ///
+    /// Returns `None` if the file is missing, empty or was moved aside by [`CorruptionPolicy::Backup`]
+    /// (or would be, when not `writable`).
+    fn read_snapshot(&mut self, writable: bool) -> io::Result<Option<Loaded<T>>> {
+        let Some(buff) = self.storage.read("")? else {
+            return Ok(None);
+        };
+        if buff.iter().all(u8::is_ascii_whitespace) {
+            return Ok(None);
+        }
+        let buff: Vec<u8> = match self.decode_file(buff, &self.storage.locate("")) {
+            Ok(b) => b,
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                return self.snapshot_corrupted(IntegrityError::Unreadable { reason: e.to_string() }, writable);
+            },
+            Err(e) => return Err(e),
+        };
+        let mut parsed: format::Parsed = match format::parse(&buff, self.config.codec.as_ref()) {
+            Ok(parsed) => parsed,
+            Err(e) => return self.snapshot_corrupted(e, writable),
+        };
+        if let Some(problem) = parsed.problems.first() {
+            return self.snapshot_corrupted(problem.clone(), writable);
+        }
+
+        let migrated_from: Option<u32> = self.migrate(&mut parsed, &self.storage.locate(""))?;
+        let header: Option<Header> = parsed.header;
+        match parsed.into_docs() {
+            Ok(docs) => Ok(Some(Loaded { docs, header, migrated_from })),
+            Err(e) => self.snapshot_corrupted(e, writable),
+        }
+    }
+
+    /// Upgrades the documents read from `path` to [`Config::schema_version`] and returns the version they were upgraded from.
+    fn migrate(&self, parsed: &mut format::Parsed, path: &Path) -> io::Result<Option<u32>> {
+        let (from, target) = (parsed.header.map_or(0, |h| h.schema), self.config.schema_version);
+        if from > target {
+            return Err(MemoError::NewerSchema { path: path.to_path_buf(), version: from }.into());
+        }
+        if from == target {
+            return Ok(None);
+        }
+        for doc in parsed.docs.values_mut() {
+            self.config.migrations.upgrade(from, target, doc)?;
+        }
+        Ok(Some(from))
+    }
+
+    fn snapshot_corrupted(&mut self, problem: IntegrityError, writable: bool) -> io::Result<Option<Loaded<T>>> {
+        let path: PathBuf = self.storage.locate("");
+        let err: StdError = match problem {
+            IntegrityError::UnsupportedFormat { version } => return Err(MemoError::UnsupportedFormat { path, version }.into()),
+            problem => MemoError::Corrupted { path, reason: problem.to_string() }.into(),
+        };
+        if !writable && self.recoverable(&err) {
+            return Ok(None);
+        }
+        self.recover(err, "")?;
+        Ok(None)
+    }
+
+    /// Copies the snapshot and journal to `<file>.<unix millis>.v<from>` before an upgrade overwrites them.
+    fn backup_before_migration(&mut self, from: u32) -> io::Result<()> {
+        let suffix: String = format!("v{from}");
+        self.storage.copy_aside("", &suffix)?;
+        if self.config.mode == StorageMode::Journal {
+            self.storage.copy_aside(journal::KEY, &suffix)?;
+        }
+        Ok(())
+    }
+
+    fn recoverable(&self, err: &StdError) -> bool {
+        self.config.on_corruption == CorruptionPolicy::Backup
+            && matches!(MemoError::from_io(err), Some(MemoError::Corrupted { .. }))
+    }
+
+    /// Moves `key`, whose contents caused `err`, aside if the policy allows it, otherwise hands `err` back.
+    fn recover(&mut self, err: StdError, key: &str) -> io::Result<()> {
+        if !self.recoverable(&err) {
+            return Err(err);
+        }
+        let backup: PathBuf = self.storage.move_aside(key, "corrupt")?;
+        self.recovered.push(backup);
+        Ok(())
+    }
+
+    fn sync_level(&self) -> SyncLevel {
+        match self.config.durability {
+            Durability::None => SyncLevel::None,
+            Durability::Flush => SyncLevel::Data,
+            Durability::Fsync => SyncLevel::All,
+            Durability::FsyncInterval(interval) => match self.last_sync {
+                Some(t) if t.elapsed() < interval => SyncLevel::None,
+                _ => SyncLevel::All,
+            },
+        }
+    }
+
+    fn synced(&mut self, level: SyncLevel) {
+        if level == SyncLevel::All {
+            self.last_sync = Some(Instant::now());
+        }
+    }
+
+    /// Writes `bytes` under `key`, or removes it for `None`.
+    fn write_key(&mut self, key: &str, bytes: Option<&[u8]>) -> io::Result<()> {
+        let level: SyncLevel = self.sync_level();
+        match bytes {
+            Some(bytes) => self.storage.write(key, bytes, level)?,
+            None => self.storage.remove(key, level)?,
+        }
+        self.synced(level);
+        self.seen = self.fingerprint()?;
+        Ok(())
+    }
+
+    fn write_snapshot(&mut self) -> io::Result<()> {
+        if self.per_document() {
+            return self.write_dir();
+        }
+        if self.config.mode == StorageMode::Lines {
+            let level: SyncLevel = self.sync_level();
+            let mut encoded: Option<(u64, journal::Offsets)> = None;
+            self.storage.write_with("", level, &mut |out| {
+                encoded = Some(self.encode_lines(out)?);
+                Ok(())
+            })?;
+            self.synced(level);
+            self.seen = self.fingerprint()?;
+            let (len, offsets) = encoded.unwrap_or_default();
+            self.journal_len = len;
+            self.torn = false;
+            self.live_len = len;
+            self.offsets = offsets;
+            return Ok(());
+        }
+        let header: Option<Header> = self.next_header();
+        let buff: Vec<u8> = self.encode_file(&self.docs, header.as_ref())?;
+        self.write_key("", Some(&buff))?;
+        self.header = header;
+        Ok(())
+    }
+
+    /// Encodes `docs` with the configured codec and compression into the bytes of a database file.
+    fn encode_file(&self, docs: &HashMap<String, T>, header: Option<&Header>) -> io::Result<Vec<u8>> {
+        let buff: Vec<u8> = format::encode(docs, header, self.config.codec.as_ref())?;
+        crypto::encrypt(compress::compress(buff, self.config.compression)?, &self.config)
+    }
+
+    /// Undoes the encryption and compression of the file read from `path`.
+    fn decode_file(&self, bytes: Vec<u8>, path: &Path) -> io::Result<Vec<u8>> {
+        compress::decompress(crypto::decrypt(bytes, &self.config, path)?)
+    }
+
+    /// The header for the next write of the file, `None` for a plain file.
+    fn next_header(&self) -> Option<Header> {
+        let enveloped: bool = self.config.envelope || self.config.schema_version > 0;
+        enveloped.then(|| Header::next(self.header.as_ref(), self.config.schema_version))
+    }
+
+    /// Whether every document is stored under its own key.
+    fn per_document(&self) -> bool {
+        matches!(self.config.mode, StorageMode::Directory | StorageMode::Bitcask)
+    }
+
+    fn is_lazy(&self) -> bool {
+        self.config.lazy
+    }
+
+    /// Whether a lazy database holds a document under `id`, read or not.
+    fn stored(&self, id: &str) -> io::Result<bool> {
+        match self.config.mode {
+            StorageMode::Lines => Ok(self.offsets.contains_key(id)),
+            _ => Ok(self.storage.fingerprint(id)?.is_some()),
+        }
+    }
+
+    /// Reads the document of a lazy database from storage: from its own key, or from its `put` line
+    /// in [`StorageMode::Lines`].
+    fn read_doc(&self, id: &str) -> io::Result<Option<T>> {
+        if self.config.mode == StorageMode::Lines {
+            let Some(line) = self.read_line(id)? else {
+                return Ok(None);
+            };
+            return self.parse_line(id, &line).map(Some);
+        }
+        let Some(buff) = self.storage.read(id)? else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&buff)
+            .map(Some)
+            .map_err(|e| MemoError::Corrupted { path: self.storage.locate(id), reason: e.to_string() }.into())
+    }
+
+    /// The `put` line of `id` in a lazy [`StorageMode::Lines`] file, without its newline.
+    fn read_line(&self, id: &str) -> io::Result<Option<Vec<u8>>> {
+        let Some(&(offset, len)) = self.offsets.get(id) else {
+            return Ok(None);
+        };
+        match self.storage.read_range("", offset, len)? {
+            Some(line) if line.len() == len => Ok(Some(line)),
+            _ => Err(self.bad_line(format!("record of {id} at byte {offset} was cut short"))),
+        }
+    }
+
+    /// The document in the `put` line of `id`.
+    fn parse_line(&self, id: &str, line: &[u8]) -> io::Result<T> {
+        match serde_json::from_slice(line) {
+            Ok(journal::Record::Put { id: stored, doc }) if stored == id => Ok(doc),
+            Ok(_) => Err(self.bad_line(format!("record at the offset of {id} isn't its put"))),
+            Err(e) => Err(self.bad_line(e.to_string())),
+        }
+    }
+
+    /// The error for an indexed line that doesn't hold the `put` record it should: a
+    /// [`MemoError::Conflict`] if another process rewrote the file since it was indexed, as the
+    /// offsets are stale then, and [`MemoError::Corrupted`] otherwise.
+    fn bad_line(&self, reason: String) -> StdError {
+        match self.changed_on_disk() {
+            Ok(true) => MemoError::Conflict { path: self.storage.locate("") }.into(),
+            Ok(false) => MemoError::Corrupted { path: self.storage.locate(""), reason }.into(),
+            Err(e) => e,
+        }
+    }
+
+    /// Writes `docs` to `out` as one `put` record per line, sorted by id so rewrites are stable, and
+    /// returns the length written and where each line went. A lazy database copies the lines of the
+    /// documents it didn't read from the current file, one at a time.
+    fn encode_lines(&self, out: &mut dyn Write) -> io::Result<(u64, journal::Offsets)> {
+        let mut ids: Vec<&String> = match self.is_lazy() {
+            true => self.offsets.keys().chain(self.docs.keys()).collect(),
+            false => self.docs.keys().collect(),
+        };
+        ids.sort();
+        ids.dedup();
+        let mut source: Option<Box<dyn ReadSeek>> = match self.is_lazy() && !self.offsets.is_empty() {
+            true => self.storage.reader("")?,
+            false => None,
+        };
+        let mut len: u64 = 0;
+        let mut offsets: journal::Offsets = HashMap::new();
+        for id in ids {
+            let line: Vec<u8> = match self.docs.get(id) {
+                Some(doc) => serde_json::to_vec(&journal::Record::Put { id: id.clone(), doc })?,
+                None => self.copy_line(&mut source, id)?,
+            };
+            out.write_all(&line)?;
+            out.write_all(b"\n")?;
+            offsets.insert(id.clone(), (len, line.len()));
+            len += line.len() as u64 + 1;
+        }
+        Ok((len, offsets))
+    }
+
+    /// The `put` line of `id` read through `source`, the open [`StorageMode::Lines`] file.
+    fn copy_line(&self, source: &mut Option<Box<dyn ReadSeek>>, id: &str) -> io::Result<Vec<u8>> {
+        let (offset, len): (u64, usize) = self.offsets.get(id).copied().unwrap_or_default();
+        let mut line: Vec<u8> = Vec::with_capacity(len);
+        if let Some(source) = source {
+            source.seek(SeekFrom::Start(offset))?;
+            source.take(len as u64).read_to_end(&mut line)?;
+        }
+        match line.len() == len {
+            true => Ok(line),
+            false => Err(self.bad_line(format!("record of {id} at byte {offset} was cut short"))),
+        }
+    }
+
+    /// A document as stored under its own key: a readable file in [`StorageMode::Directory`], compact json in a [`StorageMode::Bitcask`] record.
+    fn encode_doc(&self, doc: &T) -> io::Result<Vec<u8>> {
+        if self.config.mode == StorageMode::Bitcask {
+            return Ok(serde_json::to_vec(doc)?);
+        }
+        let mut buff: Vec<u8> = serde_json::to_vec_pretty(doc)?;
+        buff.push(b'\n');
+        Ok(buff)
+    }
+
+    /// Reads every document of a lazy database, the lines of a [`StorageMode::Lines`] file through one handle.
+    fn read_all(&self) -> io::Result<HashMap<String, T>> {
+        let mut docs: HashMap<String, T> = HashMap::new();
+        if self.config.mode == StorageMode::Lines {
+            let mut source: Option<Box<dyn ReadSeek>> = self.storage.reader("")?;
+            for id in self.offsets.keys() {
+                let line: Vec<u8> = self.copy_line(&mut source, id)?;
+                docs.insert(id.clone(), self.parse_line(id, &line)?);
+            }
+            return Ok(docs);
+        }
+        for id in self.ids()? {
+            if let Some(doc) = self.read_doc(&id)? {
+                docs.insert(id, doc);
+            }
+        }
+        Ok(docs)
+    }
+
+    /// Makes the directory hold exactly `docs`, which must be every document. Files are written one at
+    /// a time, so unlike the other modes a crash can leave some documents replaced and others not.
+    fn write_dir(&mut self) -> io::Result<()> {
+        let level: SyncLevel = self.sync_level();
+        for id in self.storage.list()? {
+            if !self.docs.contains_key(&id) {
+                self.storage.remove(&id, level)?;
+            }
+        }
+        for (id, doc) in &self.docs {
+            self.storage.write(id, &self.encode_doc(doc)?, level)?;
+        }
+        self.synced(level);
+        self.seen = self.fingerprint()?;
+        Ok(())
+    }
+
+    /// The document stored under `id`, read from storage if the database is lazy.
+    fn current(&self, id: &str) -> io::Result<Option<T>> {
+        match self.docs.get(id) {
+            Some(doc) => Ok(Some(doc.clone())),
+            None if self.is_lazy() => self.read_doc(id),
+            None => Ok(None),
+        }
+    }
+
+    /// The document stored under `id` as `changes` left it.
+    fn staged(&self, changes: &BTreeMap<String, Option<T>>, id: &str) -> io::Result<Option<T>> {
+        match changes.get(id) {
+            Some(changed) => Ok(changed.clone()),
+            None => self.current(id),
+        }
+    }
+
+    /// Checks `op` against the database as `changes` left it and adds its change to them. Returns
+    /// the previous value of the document.
+    fn stage(&self, changes: &mut BTreeMap<String, Option<T>>, op: Op<T>) -> io::Result<Option<T>> {
+        let id: String = match &op {
+            Op::Push(doc) | Op::Upsert(doc) if doc.get_id().is_empty() => uuid::Uuid::new_v4().to_string(),
+            Op::Push(doc) | Op::Replace(doc) | Op::Upsert(doc) => doc.get_id().to_string(),
+            Op::Del(id) => id.clone(),
+        };
+        let (current, change): (Option<T>, Option<T>) = match (op, self.staged(changes, &id)?) {
+            (Op::Push(_), Some(_)) => return Err(StdError::new(ErrorKind::AlreadyExists, "data already exists")),
+            (Op::Replace(_) | Op::Del(_), None) => {
+                return Err(StdError::new(ErrorKind::NotFound, format!("Data with specified ID ({id}) was not found.")));
+            },
+            (Op::Push(mut doc) | Op::Replace(mut doc) | Op::Upsert(mut doc), current) => {
+                doc.set_id(&id);
+                (current, Some(doc))
+            },
+            (Op::Del(_), current) => (current, None),
+        };
+        changes.insert(id, change);
+        Ok(current)
+    }
+
+    /// Writes `data` over whatever is stored under its id. The caller holds the exclusive lock.
+    fn put_locked(&mut self, data: T) -> io::Result<()> {
+        self.commit(BTreeMap::from([(data.get_id().to_string(), Some(data))]))
+    }
+
+    /// Writes `changes`, the new document or `None` to delete it by id, in a single write: one
+    /// append in the log modes, one snapshot otherwise. The per-document modes write one key per
+    /// change and put back the ones already written if a later one fails. `docs` is left unchanged
+    /// on failure. The caller holds the exclusive lock.
+    fn commit(&mut self, changes: BTreeMap<String, Option<T>>) -> io::Result<()> {
+        let mut records: Vec<String> = Vec::new();
+        if self.log_key().is_some() {
+            for (id, doc) in &changes {
+                records.push(match doc {
+                    Some(doc) => serde_json::to_string(&journal::Record::Put { id: id.clone(), doc })?,
+                    None => serde_json::to_string(&journal::Record::<&T>::Del { id: id.clone() })?,
+                });
+            }
+        }
+        // What the per-document modes write under each key, `None` to remove it, and what the keys
+        // held before, to put back if a later write fails.
+        let mut keys: Vec<(String, Option<Vec<u8>>)> = Vec::new();
+        let mut prior: Vec<Option<Vec<u8>>> = Vec::new();
+        if self.per_document() {
+            for (id, doc) in &changes {
+                keys.push((id.clone(), doc.as_ref().map(|doc| self.encode_doc(doc)).transpose()?));
+                if changes.len() > 1 {
+                    prior.push(self.storage.read(id)?);
+                }
+            }
+        }
+        // Applied first so the snapshot, or a compaction triggered by the append, includes the changes.
+        // A lazy database reads documents from storage instead, and only caches the written ones.
+        let lazy: bool = self.is_lazy();
+        let mut cached: Vec<(String, T)> = Vec::new();
+        let mut loaded: Vec<(String, bool, Option<T>)> = Vec::with_capacity(changes.len());
+        for (id, doc) in changes {
+            let put: bool = doc.is_some();
+            let old: Option<T> = match doc {
+                Some(doc) if lazy => {
+                    cached.push((id.clone(), doc));
+                    self.docs.remove(&id)
+                },
+                Some(doc) => self.docs.insert(id.clone(), doc),
+                None => self.docs.remove(&id),
+            };
+            loaded.push((id, put, old));
+        }
+        let written: io::Result<()> = match self.log_key() {
+            Some(log) => self.append(log, &records).map(|offsets| {
+                if self.config.mode != StorageMode::Lines {
+                    return;
+                }
+                for ((id, put, _), (offset, record)) in loaded.iter().zip(offsets.into_iter().zip(&records)) {
+                    // The line of the replaced or deleted document is garbage now.
+                    let replaced: Option<(u64, usize)> = match put {
+                        true => self.offsets.insert(id.clone(), (offset, record.len())),
+                        false => self.offsets.remove(id),
+                    };
+                    if let Some((_, len)) = replaced {
+                        self.live_len -= len as u64 + 1;
+                    }
+                    if *put {
+                        self.live_len += record.len() as u64 + 1;
+                    }
+                }
+            }),
+            None if self.per_document() => self.write_docs(&keys, &prior),
+            None => self.write_snapshot(),
+        };
+        if let Err(e) = written {
+            for (id, _, old) in loaded {
+                match old {
+                    Some(doc) => self.docs.insert(id, doc),
+                    None => self.docs.remove(&id),
+                };
+            }
+            return Err(e);
+        }
+        for (id, ..) in &loaded {
+            self.cache.remove(id);
+        }
+        for (id, doc) in cached {
+            self.cache.insert(&id, doc);
+        }
+        self.compact_if_due();
+        Ok(())
+    }
+
+    /// Writes or removes the key of every changed document, putting `prior` back if one fails.
+    fn write_docs(&mut self, keys: &[(String, Option<Vec<u8>>)], prior: &[Option<Vec<u8>>]) -> io::Result<()> {
+        for (i, (id, bytes)) in keys.iter().enumerate() {
+            let Err(e) = self.write_key(id, bytes.as_deref()) else {
+                continue;
+            };
+            for ((id, _), bytes) in keys[..i].iter().zip(prior) {
+                if let Err(e) = self.write_key(id, bytes.as_deref()) {
+                    self.defer(e);
+                }
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The key records are appended to, `None` for [`StorageMode::Snapshot`].
+    fn log_key(&self) -> Option<&'static str> {
+        match self.config.mode {
+            StorageMode::Snapshot | StorageMode::Directory | StorageMode::Bitcask => None,
+            StorageMode::Journal => Some(journal::KEY),
+            StorageMode::Lines => Some(""),
+        }
+    }
+
+    /// Appends `records` to the log under `log` in one write and returns the offset of each. More than one are
+    /// preceded by a `begin` record, so that a crash halfway through leaves none of them.
+    fn append(&mut self, log: &str, records: &[String]) -> io::Result<Vec<u64>> {
+        let mut buff: Vec<u8> = Vec::new();
+        if records.len() > 1 {
+            serde_json::to_writer(&mut buff, &journal::Record::<()>::Begin { count: records.len() })?;
+            buff.push(b'\n');
+        }
+        let mut offsets: Vec<u64> = Vec::with_capacity(records.len());
+        for record in records {
+            offsets.push(self.journal_len + buff.len() as u64);
+            buff.extend_from_slice(record.as_bytes());
+            buff.push(b'\n');
+        }
+        if self.torn {
+            self.cut_log(log)?;
+        }
+        let level: SyncLevel = self.sync_level();
+        if let Err(e) = self.storage.append(log, &buff, level) {
+            // Part of the records may have landed, or all of them without being synced. Either way
+            // they count as never written, and the next ones must not start after their remains.
+            if self.cut_log(log).is_err() {
+                // Makes the next write go through `on_conflict` as if another process changed the log.
+                self.seen = [None, None];
+                self.torn = true;
+            }
+            return Err(e);
+        }
+        self.journal_len += buff.len() as u64;
+        self.synced(level);
+        self.seen = self.fingerprint()?;
+        // The records are durable at this point, a failed compaction is simply retried on the next append.
+        // A lines file holds the live documents too, so it is only rewritten by `compact`.
+        if self.config.mode == StorageMode::Journal && self.journal_len > self.config.journal_limit {
+            if let Err(e) = self.fold_journal() {
+                self.defer(e);
+            }
+        }
+        Ok(offsets)
+    }
+
+    /// Cuts the log back to the records this database wrote or loaded, dropping a torn tail.
+    fn cut_log(&mut self, log: &str) -> io::Result<()> {
+        let Some(bytes) = self.storage.read(log)? else {
+            return Ok(());
+        };
+        if bytes.len() as u64 > self.journal_len {
+            self.storage.write(log, &bytes[..self.journal_len as usize], SyncLevel::All)?;
+            self.seen = self.fingerprint()?;
+        }
+        self.torn = false;
+        Ok(())
     }
 }
@@ -0,0 +1,51 @@
+use std::{fs::{File, TryLockError}, io, path::Path, thread, time::{Duration, Instant}};
+
+use crate::{LockMode, MemoError};
+
+/// Holds an advisory lock on a `.lock` file until dropped, closing the file releases it.
+///
+/// The lock lives in a sidecar file because the database file itself is replaced on every write,
+/// and a lock on the replaced inode would not be seen by the next process.
+#[derive(Debug)]
+pub(crate) struct FileLock {
+    _file: File,
+}
+
+/// Takes a shared (`exclusive == false`) or exclusive lock on `path` as described by `mode`,
+/// returning `None` for [`LockMode::None`].
+pub(crate) fn acquire(path: &Path, exclusive: bool, mode: LockMode) -> io::Result<Option<FileLock>> {
+    if mode == LockMode::None {
+        return Ok(None);
+    }
+    let file: File = File::options().read(true).write(true).create(true).truncate(false).open(path)?;
+    let try_lock = |file: &File| -> io::Result<bool> {
+        let locked: Result<(), TryLockError> = if exclusive { file.try_lock() } else { file.try_lock_shared() };
+        match locked {
+            Ok(()) => Ok(true),
+            Err(TryLockError::WouldBlock) => Ok(false),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    };
+
+    match mode {
+        LockMode::None => {},
+        LockMode::Blocking if exclusive => file.lock()?,
+        LockMode::Blocking => file.lock_shared()?,
+        LockMode::Try => {
+            if !try_lock(&file)? {
+                return Err(MemoError::Locked { path: path.to_path_buf() }.into());
+            }
+        },
+        LockMode::Timeout(timeout) => {
+            let deadline: Instant = Instant::now() + timeout;
+            while !try_lock(&file)? {
+                let now: Instant = Instant::now();
+                if now >= deadline {
+                    return Err(MemoError::Locked { path: path.to_path_buf() }.into());
+                }
+                thread::sleep((deadline - now).min(Duration::from_millis(10)));
+            }
+        },
+    }
+    Ok(Some(FileLock { _file: file }))
+}
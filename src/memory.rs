@@ -0,0 +1,124 @@
+use std::{collections::{BTreeMap, HashMap}, io::{self, ErrorKind}, path::PathBuf, sync::{Mutex, MutexGuard}};
+
+use crate::{disk::{self, SyncLevel}, LockGuard, LockMode, Storage};
+
+/// What [`MemoryStorage::locate`] names the database, nothing is ever written there.
+const NAME: &str = "memory";
+
+#[derive(Debug, Default)]
+struct Objects {
+    /// Every object with the generation it was last written in.
+    live: BTreeMap<String, (Vec<u8>, u64)>,
+    /// Objects moved or copied aside, by the location reported for them.
+    aside: HashMap<PathBuf, Vec<u8>>,
+    /// Bumped by every change.
+    generation: u64,
+}
+
+#[doc = r#"A [`Storage`] keeping every object in memory, used by [`DataBase::in_memory`](crate::DataBase::in_memory).
+
+Nothing touches the disk and every [`SyncLevel`] is a no-op. Objects moved or copied aside, like a
+corrupt file under [`CorruptionPolicy::Backup`](crate::CorruptionPolicy::Backup), are kept in
+memory too. No lock is taken, so handles sharing one storage must not write concurrently; the
+fingerprint of the empty key changes with every write, so they still notice each other's changes.
+"#]
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    objects: Mutex<Objects>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn objects(&self) -> MutexGuard<'_, Objects> {
+        // Nothing can panic halfway through a change, so a poisoned lock still guards consistent maps.
+        self.objects.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Objects {
+    fn set(&mut self, key: &str, bytes: Vec<u8>) {
+        self.generation += 1;
+        self.live.insert(key.to_string(), (bytes, self.generation));
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.objects().live.get(key).map(|(bytes, _)| bytes.clone()))
+    }
+
+    fn write(&self, key: &str, bytes: &[u8], _level: SyncLevel) -> io::Result<()> {
+        self.objects().set(key, bytes.to_vec());
+        Ok(())
+    }
+
+    fn append(&self, key: &str, bytes: &[u8], _level: SyncLevel) -> io::Result<()> {
+        let mut objects = self.objects();
+        let mut buff: Vec<u8> = objects.live.remove(key).map(|(b, _)| b).unwrap_or_default();
+        buff.extend_from_slice(bytes);
+        objects.set(key, buff);
+        Ok(())
+    }
+
+    fn remove(&self, key: &str, _level: SyncLevel) -> io::Result<()> {
+        let mut objects = self.objects();
+        if objects.live.remove(key).is_some() {
+            objects.generation += 1;
+        }
+        Ok(())
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        Ok(self.objects().live.keys().cloned().collect())
+    }
+
+    fn lock(&self, _exclusive: bool, _mode: LockMode) -> io::Result<Option<LockGuard>> {
+        Ok(None)
+    }
+
+    /// The generation of the last change to any object for the empty key, as it stands for the
+    /// whole database in [`StorageMode::Directory`](crate::StorageMode::Directory).
+    fn fingerprint(&self, key: &str) -> io::Result<Option<u64>> {
+        let objects = self.objects();
+        Ok(match key {
+            "" => Some(objects.generation).filter(|_| !objects.live.is_empty()),
+            key => objects.live.get(key).map(|(_, generation)| *generation),
+        })
+    }
+
+    fn move_aside(&self, key: &str, suffix: &str) -> io::Result<PathBuf> {
+        let mut objects = self.objects();
+        let Some((bytes, _)) = objects.live.remove(key) else {
+            return Err(io::Error::new(ErrorKind::NotFound, format!("no object under key ({key}) to move aside")));
+        };
+        objects.generation += 1;
+        let dest: PathBuf = disk::timestamped(&self.locate(key), suffix);
+        objects.aside.insert(dest.clone(), bytes);
+        Ok(dest)
+    }
+
+    fn copy_aside(&self, key: &str, suffix: &str) -> io::Result<Option<PathBuf>> {
+        let mut objects = self.objects();
+        let Some((bytes, _)) = objects.live.get(key) else {
+            return Ok(None);
+        };
+        let bytes: Vec<u8> = bytes.clone();
+        let dest: PathBuf = disk::timestamped(&self.locate(key), suffix);
+        objects.aside.insert(dest.clone(), bytes);
+        Ok(Some(dest))
+    }
+
+    fn locate(&self, key: &str) -> PathBuf {
+        match key {
+            "" => PathBuf::from(NAME),
+            key => disk::sidecar(&PathBuf::from(NAME), key),
+        }
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::{collections::BTreeMap, fmt, io, sync::Arc};
+use serde_json::Value;
+
+use crate::MemoError;
+
+type Step = Arc<dyn Fn(&mut Value) -> io::Result<()> + Send + Sync>;
+
+#[doc = r#"Ordered upgrades of stored documents from one [`Config::schema_version`](crate::Config::schema_version) to the next.
+
+Each step receives a document as a `serde_json::Value` before it is de-serialized into `T`.
+When `open` finds a file whose header records an older schema version (plain files count as
+version 0), it copies the file to `<file>.<unix millis>.v<old version>`, runs every step up to
+`schema_version` over the snapshot and journal, and writes the upgraded file right away.
+Files with a newer schema version than `schema_version` are refused.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, MemoDoc, Migrations};
+use memorable_macro_derive::MemoDoc;
+
+// Version 1 renamed `name` to `title`.
+#[derive(MemoDoc, Serialize, Deserialize, Clone, Debug)]
+struct Task {
+    uuid: String,
+    title: String
+}
+
+fn main() {
+    # let dir = std::env::temp_dir().join("memorable_doc_migrations");
+    # let _ = std::fs::remove_dir_all(&dir);
+    # std::fs::create_dir_all(&dir).unwrap();
+    # let path = dir.join("db.json");
+    # let path = path.to_str().unwrap();
+    std::fs::write(path, "{ \"a\": { \"uuid\": \"a\", \"name\": \"write docs\" } }").unwrap();
+
+    let migrations = Migrations::new().add(0, |doc| {
+        let name = doc["name"].take();
+        doc["title"] = name;
+        doc.as_object_mut().unwrap().remove("name");
+        Ok(())
+    });
+    let config = Config { schema_version: 1, migrations, ..Config::default() };
+    let f: DataBase<Task> = DataBase::open_with(path, config).unwrap();
+    assert_eq!(f.docs["a"].title, "write docs");
+    assert_eq!(f.header().unwrap().schema, 1);
+    // The original file plus its pre-migration copy.
+    assert_eq!(std::fs::read_dir(&dir).unwrap().filter(|e| {
+        e.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "v0")
+    }).count(), 1);
+}
+```"#]
+#[derive(Clone, Default)]
+pub struct Migrations {
+    steps: BTreeMap<u32, Step>,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the step that upgrades a document from schema version `from` to `from + 1`,
+    /// replacing any step already registered for `from`.
+    pub fn add(mut self, from: u32, step: impl Fn(&mut Value) -> io::Result<()> + Send + Sync + 'static) -> Self {
+        self.steps.insert(from, Arc::new(step));
+        self
+    }
+
+    /// Runs the steps from `from` up to `to` over `doc`.
+    pub(crate) fn upgrade(&self, from: u32, to: u32, doc: &mut Value) -> io::Result<()> {
+        for version in from..to {
+            let step: &Step = self.steps.get(&version).ok_or_else(|| MemoError::Migration {
+                version,
+                reason: "no migration registered".to_string(),
+            })?;
+            step(doc).map_err(|e| MemoError::Migration { version, reason: e.to_string() })?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Migrations").field("from", &self.steps.keys().collect::<Vec<_>>()).finish()
+    }
+}
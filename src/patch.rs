@@ -0,0 +1,47 @@
+use std::io::{self, ErrorKind};
+
+use serde_json::Value;
+
+#[doc = r#"A partial update for [`DataBase::patch`](crate::DataBase::patch), behind the `patch` cargo feature.
+
+Both kinds are taken as parsed JSON, so a request body can be passed on as is; which one it is
+usually follows from its content type.
+
+# Examples
+```
+use memorable::Patch;
+use serde_json::json;
+
+// application/merge-patch+json
+let merge = Patch::Merge(json!({ "name": "Rayray", "nickname": null }));
+// application/json-patch+json
+let json = Patch::Json(json!([
+    { "op": "test", "path": "/name", "value": "Ray" },
+    { "op": "replace", "path": "/name", "value": "Rayray" },
+]));
+```"#]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Patch {
+    /// An [RFC 7396](https://www.rfc-editor.org/rfc/rfc7396) JSON Merge Patch: its members replace
+    /// those of the document, recursively, and `null` removes them.
+    Merge(Value),
+    /// An [RFC 6902](https://www.rfc-editor.org/rfc/rfc6902) JSON Patch: an array of operations,
+    /// applied all or nothing.
+    Json(Value),
+}
+
+impl Patch {
+    /// Applies the patch to `doc`, leaving it unchanged if that fails.
+    pub(crate) fn apply(&self, doc: &mut Value) -> io::Result<()> {
+        match self {
+            Patch::Merge(patch) => json_patch::merge(doc, patch),
+            Patch::Json(patch) => {
+                let patch: json_patch::Patch = serde_json::from_value(patch.clone())
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidInput, format!("invalid JSON Patch: {e}")))?;
+                json_patch::patch(doc, &patch).map_err(|e| io::Error::new(ErrorKind::InvalidInput, format!("JSON Patch failed: {e}")))?;
+            },
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+use std::{any::Any, fmt::Debug, fs::{self, File}, io::{self, BufReader, Cursor, ErrorKind, Read, Seek, Write}, path::PathBuf};
+
+use crate::{disk::{self, SyncLevel}, lock, LockMode};
+
+/// Held for as long as a [`Storage`] lock is taken, dropping it releases the lock.
+pub type LockGuard = Box<dyn Any + Send>;
+
+/// An object opened with [`Storage::reader`], read from any offset.
+pub trait ReadSeek: Read + Seek {}
+
+impl<R: Read + Seek> ReadSeek for R {}
+
+/// Space taken by a [`Storage`], in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Usage {
+    /// Everything stored, including overwritten and removed data not yet reclaimed.
+    pub total: u64,
+    /// Only the current version of every object.
+    pub live: u64,
+}
+
+impl Usage {
+    /// The share of `total` that compaction would reclaim, 0 for an empty storage.
+    pub fn garbage_ratio(&self) -> f64 {
+        match self.total {
+            0 => 0.0,
+            total => total.saturating_sub(self.live) as f64 / total as f64,
+        }
+    }
+}
+
+/// What [`DataBase::compact`](crate::DataBase::compact) did, as the [`Usage::total`] before and after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Compaction {
+    pub before: u64,
+    pub after: u64,
+}
+
+impl Compaction {
+    /// Bytes freed by the compaction.
+    pub fn reclaimed(&self) -> u64 {
+        self.before.saturating_sub(self.after)
+    }
+}
+
+#[doc = r#"Where a [`DataBase`](crate::DataBase) keeps its bytes.
+
+A storage is a set of byte objects under string keys plus a lock shared by every handle on it.
+A single-file database is stored under the empty key, with its journal under `"journal"`. In
+[`StorageMode::Directory`](crate::StorageMode::Directory) every document is stored under its id,
+and the fingerprint of the empty key must change whenever any of them does.
+
+[`FileStorage`] and [`DirStorage`](crate::DirStorage) are what [`DataBase::open_with`](crate::DataBase::open_with)
+uses; implement this trait and open with [`DataBase::open_with_storage`](crate::DataBase::open_with_storage)
+to keep the database elsewhere.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, FileStorage, LockGuard, LockMode, MemoDoc, Storage, SyncLevel};
+use memorable_macro_derive::MemoDoc;
+use std::{io, path::PathBuf, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+/// Counts the writes made to a file storage.
+#[derive(Debug)]
+struct Counted(FileStorage, AtomicUsize);
+
+impl Storage for Counted {
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> { self.0.read(key) }
+    fn write(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        self.0.write(key, bytes, level)
+    }
+    fn append(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> { self.0.append(key, bytes, level) }
+    fn remove(&self, key: &str, level: SyncLevel) -> io::Result<()> { self.0.remove(key, level) }
+    fn list(&self) -> io::Result<Vec<String>> { self.0.list() }
+    fn lock(&self, exclusive: bool, mode: LockMode) -> io::Result<Option<LockGuard>> { self.0.lock(exclusive, mode) }
+    fn fingerprint(&self, key: &str) -> io::Result<Option<u64>> { self.0.fingerprint(key) }
+    fn move_aside(&self, key: &str, suffix: &str) -> io::Result<PathBuf> { self.0.move_aside(key, suffix) }
+    fn copy_aside(&self, key: &str, suffix: &str) -> io::Result<Option<PathBuf>> { self.0.copy_aside(key, suffix) }
+    fn locate(&self, key: &str) -> PathBuf { self.0.locate(key) }
+    fn sync(&self) -> io::Result<()> { self.0.sync() }
+}
+
+fn main() {
+    # let path = std::env::temp_dir().join("memorable_doc_storage.json");
+    # let _ = std::fs::remove_file(&path);
+    # pollster::block_on(async {
+    let storage = Arc::new(Counted(FileStorage::new(path), AtomicUsize::new(0)));
+    let mut f: DataBase<Task> = DataBase::open_with_storage(storage.clone(), Config::default()).unwrap();
+    f.push(Task::default()).await.unwrap();
+    // Creating the file on open, then the push.
+    assert_eq!(storage.1.load(Ordering::SeqCst), 2);
+    # });
+}
+```"#]
+pub trait Storage: Debug + Send + Sync {
+    /// Reads the whole object under `key`, `None` if there is none.
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+    /// Reads up to `len` bytes of the object under `key` from `offset` on, fewer at its end, `None`
+    /// if there is none. Reads the whole object by default.
+    fn read_range(&self, key: &str, offset: u64, len: usize) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.read(key)?.map(|bytes| {
+            let start: usize = usize::try_from(offset).unwrap_or(usize::MAX).min(bytes.len());
+            bytes[start..bytes.len().min(start.saturating_add(len))].to_vec()
+        }))
+    }
+    /// Opens the object under `key` to read parts of it through one handle, `None` if there is none.
+    /// Reads the whole object into memory by default.
+    fn reader(&self, key: &str) -> io::Result<Option<Box<dyn ReadSeek>>> {
+        Ok(self.read(key)?.map(|bytes| Box::new(Cursor::new(bytes)) as Box<dyn ReadSeek>))
+    }
+    /// Replaces the object under `key` with `bytes`, so that a crash leaves either the old or the new
+    /// object, never a mix of both.
+    fn write(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()>;
+    /// Replaces the object under `key` with what `fill` writes, like [`Storage::write`]. Nothing is
+    /// replaced if `fill` fails. Collects the bytes in memory and writes them by default.
+    fn write_with(&self, key: &str, level: SyncLevel, fill: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+        let mut bytes: Vec<u8> = Vec::new();
+        fill(&mut bytes)?;
+        self.write(key, &bytes, level)
+    }
+    /// Appends `bytes` to the object under `key`, creating it if there is none.
+    fn append(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()>;
+    /// Removes the object under `key`. Removing a missing object is not an error.
+    fn remove(&self, key: &str, level: SyncLevel) -> io::Result<()>;
+    /// Lists the keys of all objects, sorted.
+    fn list(&self) -> io::Result<Vec<String>>;
+    /// Takes the shared (`exclusive == false`) or exclusive lock as described by `mode`, `None` for [`LockMode::None`].
+    fn lock(&self, exclusive: bool, mode: LockMode) -> io::Result<Option<LockGuard>>;
+    /// Identifies the current version of the object under `key`, `None` if there is none. It must
+    /// change with every write, append and removal, including those made by other processes.
+    fn fingerprint(&self, key: &str) -> io::Result<Option<u64>>;
+    /// Moves the object under `key` out of the way under `suffix`, so it is no longer read or listed,
+    /// and returns where it went.
+    fn move_aside(&self, key: &str, suffix: &str) -> io::Result<PathBuf>;
+    /// Copies the object under `key` aside under `suffix` and returns where it went, `None` if there is none.
+    fn copy_aside(&self, key: &str, suffix: &str) -> io::Result<Option<PathBuf>>;
+    /// Where the object under `key` lives, for messages and errors.
+    fn locate(&self, key: &str) -> PathBuf;
+    /// Forces every object, and the storage's own metadata, to durable storage.
+    fn sync(&self) -> io::Result<()>;
+    /// The space taken by every listed object. Both totals are the objects' lengths by default,
+    /// storages that keep overwritten data around report it as not live.
+    fn usage(&self) -> io::Result<Usage> {
+        let mut total: u64 = 0;
+        for key in self.list()? {
+            total += self.read(&key)?.map_or(0, |b| b.len() as u64);
+        }
+        Ok(Usage { total, live: total })
+    }
+    /// Reclaims the space still taken by overwritten and removed objects, for storages that keep
+    /// them around. The caller holds the exclusive lock. Does nothing by default.
+    fn compact(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[doc = r#"A [`Storage`] keeping the database in the file at `path`, and any other key in the sidecar file `<path>.<key>`.
+
+Writes go through a temp file that is renamed over the target, and the lock is taken on `<path>.lock`."#]
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        match key {
+            "" => self.path.clone(),
+            key => disk::sidecar(&self.path, key),
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        disk::read(&self.path(key))
+    }
+
+    fn read_range(&self, key: &str, offset: u64, len: usize) -> io::Result<Option<Vec<u8>>> {
+        disk::read_range(&self.path(key), offset, len)
+    }
+
+    fn reader(&self, key: &str) -> io::Result<Option<Box<dyn ReadSeek>>> {
+        match File::open(self.path(key)) {
+            Ok(file) => Ok(Some(Box::new(BufReader::new(file)))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+        disk::write_atomic(&self.path(key), bytes, level)
+    }
+
+    /// Streams the bytes into the temp file that is renamed over the target.
+    fn write_with(&self, key: &str, level: SyncLevel, fill: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+        disk::write_atomic_with(&self.path(key), level, fill)
+    }
+
+    fn append(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+        disk::append(&self.path(key), bytes, level)
+    }
+
+    fn remove(&self, key: &str, level: SyncLevel) -> io::Result<()> {
+        disk::remove(&self.path(key), level)
+    }
+
+    /// The empty key if the file exists, and the key of every sidecar that isn't a lock, temp file or backup.
+    fn list(&self) -> io::Result<Vec<String>> {
+        let mut keys: Vec<String> = Vec::new();
+        if self.path.exists() {
+            keys.push(String::new());
+        }
+        let name: String = disk::file_name(&self.path);
+        for entry in fs::read_dir(disk::parent(&self.path))? {
+            let file_name = entry?.file_name();
+            let key: Option<&str> = file_name
+                .to_str()
+                .and_then(|n| n.strip_prefix(name.as_str()))
+                .and_then(|n| n.strip_prefix('.'));
+            if let Some(key) = key.filter(|k| !k.is_empty() && !k.contains('.') && *k != "lock") {
+                keys.push(key.to_string());
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn lock(&self, exclusive: bool, mode: LockMode) -> io::Result<Option<LockGuard>> {
+        let guard: Option<lock::FileLock> = lock::acquire(&disk::sidecar(&self.path, "lock"), exclusive, mode)?;
+        Ok(guard.map(|g| Box::new(g) as LockGuard))
+    }
+
+    fn fingerprint(&self, key: &str) -> io::Result<Option<u64>> {
+        disk::fingerprint(&self.path(key))
+    }
+
+    fn move_aside(&self, key: &str, suffix: &str) -> io::Result<PathBuf> {
+        disk::backup(&self.path(key), suffix)
+    }
+
+    fn copy_aside(&self, key: &str, suffix: &str) -> io::Result<Option<PathBuf>> {
+        let path: PathBuf = self.path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        disk::backup_copy(&path, suffix).map(Some)
+    }
+
+    fn locate(&self, key: &str) -> PathBuf {
+        self.path(key)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        for key in self.list()? {
+            File::open(self.path(&key))?.sync_all()?;
+        }
+        disk::sync_dir(&self.path)
+    }
+
+    fn usage(&self) -> io::Result<Usage> {
+        let total: u64 = disk::size(self.list()?.iter().map(|key| self.path(key)))?;
+        Ok(Usage { total, live: total })
+    }
+}
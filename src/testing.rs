@@ -0,0 +1,365 @@
+//! Tools for testing code built on memorable, and memorable itself, against failing storage.
+
+use std::{collections::HashMap, fs, io::{self, ErrorKind}, path::PathBuf, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex}};
+
+use crate::{disk, LockGuard, LockMode, Storage, SyncLevel, Usage};
+
+/// What goes wrong in a [`FaultyStorage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// A `write` or `append` fails before any of its bytes land, like on a full disk.
+    FailWrite,
+    /// Only the first `n` bytes of a `write` or `append` land before it fails, at most all but the
+    /// last. Since `write` replaces its object atomically, its torn bytes never become visible.
+    TruncateWrite(usize),
+    /// A `write`, `append` or `remove` that asks for a [`SyncLevel`], or a `sync`, fails after the
+    /// data reached the storage.
+    FailSync,
+    /// The rename that makes a `write` visible, or a `remove` or `move_aside`, fails before taking effect.
+    FailRename,
+    /// The process dies while a `write` or `append` is writing its files, after the first `n`
+    /// bytes it adds to each reached them: a file grown in place keeps only those, and a file
+    /// created or replaced through a rename is only there if it is at most `n` bytes long.
+    /// Always crashes.
+    TornWrite(usize),
+    /// The machine loses power as a `write` or `append` starts: every file goes back to what it
+    /// held when it was last synced, by an operation asking for a [`SyncLevel`], a `sync`,
+    /// `compact`, `move_aside` or `copy_aside`. Always crashes.
+    LoseUnsynced,
+}
+
+#[doc = r#"A [`Storage`] that passes every operation through to another one, except that the `n`th
+operation [`Fault`] applies to (counting from 1) fails.
+
+With [`FaultyStorage::crash`] every operation after the fault fails too, as if the process died
+there. Reopening the database from the inner storage then shows what a crash at that point would
+have left behind.
+
+[`Fault::TornWrite`] and [`Fault::LoseUnsynced`] act on the files themselves, so they need an inner
+storage that keeps them on disk, alone in the directory holding [`Storage::locate`] of the empty
+key: every file under it is read before and after each write.
+
+# Examples
+```
+use serde::{Deserialize, Serialize};
+use memorable::{Config, DataBase, MemoDoc, MemoryStorage, Storage};
+use memorable::testing::{Fault, FaultyStorage};
+use memorable_macro_derive::MemoDoc;
+use std::sync::Arc;
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String
+}
+
+fn main() {
+    # pollster::block_on(async {
+    let inner: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    // The first write creates the database, the second is the first push.
+    let faulty = Arc::new(FaultyStorage::new(inner.clone(), Fault::FailRename, 3).crash());
+    let mut f: DataBase<Task> = DataBase::open_with_storage(faulty.clone(), Config::default()).unwrap();
+    f.push(Task { uuid: "a".into() }).await.unwrap();
+    assert!(f.push(Task { uuid: "b".into() }).await.is_err());
+    assert!(faulty.fired());
+
+    let f: DataBase<Task> = DataBase::open_with_storage(inner, Config::default()).unwrap();
+    assert!(f.docs.contains_key("a"));
+    assert!(!f.docs.contains_key("b"));
+    # });
+}
+```"#]
+#[derive(Debug)]
+pub struct FaultyStorage {
+    inner: Arc<dyn Storage>,
+    fault: Fault,
+    at: usize,
+    crash: bool,
+    /// Operations the fault applies to so far.
+    count: AtomicUsize,
+    fired: AtomicBool,
+    /// What the files held when last synced, for [`Fault::LoseUnsynced`]. Taken before the first write.
+    durable: Mutex<Option<Files>>,
+}
+
+/// The bytes of every file under a directory, with their inode where there is one to tell a
+/// file grown in place from one replaced.
+type Files = HashMap<PathBuf, (Option<u64>, Vec<u8>)>;
+
+/// The kinds of operation a [`Fault`] can hit.
+#[derive(PartialEq, Eq)]
+enum Kind {
+    Write,
+    Sync,
+    Rename,
+}
+
+impl FaultyStorage {
+    /// Wraps `inner` so that the `at`th operation `fault` applies to fails.
+    pub fn new(inner: Arc<dyn Storage>, fault: Fault, at: usize) -> Self {
+        Self { inner, fault, at, crash: false, count: AtomicUsize::new(0), fired: AtomicBool::new(false), durable: Mutex::new(None) }
+    }
+
+    /// Makes every operation after the fault fail.
+    pub fn crash(mut self) -> Self {
+        self.crash = true;
+        self
+    }
+
+    /// Whether the fault happened yet.
+    pub fn fired(&self) -> bool {
+        self.fired.load(Ordering::SeqCst)
+    }
+
+    /// Fails once the storage crashed.
+    fn alive(&self) -> io::Result<()> {
+        match (self.crash || self.on_files()) && self.fired() {
+            true => Err(io::Error::new(ErrorKind::BrokenPipe, "storage crashed after an injected fault")),
+            false => Ok(()),
+        }
+    }
+
+    /// Counts an operation of `kind` and returns whether it is the one to fail.
+    fn hits(&self, kind: Kind) -> bool {
+        let applies: Kind = match self.fault {
+            Fault::FailWrite | Fault::TruncateWrite(_) | Fault::TornWrite(_) | Fault::LoseUnsynced => Kind::Write,
+            Fault::FailSync => Kind::Sync,
+            Fault::FailRename => Kind::Rename,
+        };
+        if applies != kind || self.fired() {
+            return false;
+        }
+        let hit: bool = self.count.fetch_add(1, Ordering::SeqCst) + 1 == self.at;
+        if hit {
+            self.fired.store(true, Ordering::SeqCst);
+        }
+        hit
+    }
+
+    /// The error of the injected fault.
+    fn fault(&self) -> io::Error {
+        match self.fault {
+            Fault::FailWrite | Fault::TruncateWrite(_) => io::Error::new(ErrorKind::StorageFull, "injected fault: no space left on device"),
+            Fault::FailSync => io::Error::other("injected fault: fsync failed"),
+            Fault::FailRename => io::Error::other("injected fault: rename failed"),
+            Fault::TornWrite(_) | Fault::LoseUnsynced => io::Error::new(ErrorKind::BrokenPipe, "injected fault: crashed during the write"),
+        }
+    }
+
+    /// Whether the fault acts on the files rather than on the operations.
+    fn on_files(&self) -> bool {
+        matches!(self.fault, Fault::TornWrite(_) | Fault::LoseUnsynced)
+    }
+
+    /// Every file under the directory holding the storage.
+    fn files(&self) -> io::Result<Files> {
+        let mut files: Files = HashMap::new();
+        let mut dirs: Vec<PathBuf> = vec![disk::parent(&self.inner.locate("")).to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let entries: fs::ReadDir = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries {
+                let path: PathBuf = entry?.path();
+                let meta: fs::Metadata = fs::metadata(&path)?;
+                if meta.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                #[cfg(unix)]
+                let ino: Option<u64> = Some(std::os::unix::fs::MetadataExt::ino(&meta));
+                #[cfg(not(unix))]
+                let ino: Option<u64> = None;
+                files.insert(path.clone(), (ino, fs::read(&path)?));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Puts the files from `now` back to `then`.
+    fn put_back(now: &Files, then: &Files) -> io::Result<()> {
+        for path in now.keys().filter(|path| !then.contains_key(*path)) {
+            fs::remove_file(path)?;
+        }
+        for (path, (_, bytes)) in then {
+            if now.get(path).is_none_or(|(_, current)| current != bytes) {
+                fs::create_dir_all(disk::parent(path))?;
+                fs::write(path, bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `op`, which changes the files and syncs them to `level`, under a fault that acts on
+    /// the files. Only a `write` or `append` is `counted` towards the fault.
+    fn on_disk<R>(&self, level: SyncLevel, counted: bool, op: impl FnOnce() -> io::Result<R>) -> io::Result<R> {
+        let before: Files = self.files()?;
+        let mut durable = self.durable.lock().unwrap_or_else(|e| e.into_inner());
+        let durable: &mut Files = durable.get_or_insert_with(|| before.clone());
+        if counted && self.hits(Kind::Write) {
+            match self.fault {
+                Fault::TornWrite(n) => {
+                    let _ = op();
+                    let after: Files = self.files()?;
+                    let mut torn: Files = after.clone();
+                    for (path, (ino, bytes)) in &after {
+                        match before.get(path) {
+                            Some((_, old)) if old == bytes => continue,
+                            Some((old_ino, old)) if old_ino == ino && bytes.starts_with(old) => {
+                                torn.insert(path.clone(), (*ino, bytes[..bytes.len().min(old.len() + n)].to_vec()));
+                            },
+                            _ if bytes.len() <= n => {},
+                            Some(old) => {
+                                torn.insert(path.clone(), old.clone());
+                            },
+                            None => {
+                                torn.remove(path);
+                            },
+                        }
+                    }
+                    Self::put_back(&after, &torn)?;
+                },
+                _ => Self::put_back(&before, durable)?,
+            }
+            return Err(self.fault());
+        }
+        let done: io::Result<R> = op();
+        if level != SyncLevel::None {
+            // Syncing a file makes all of it durable, including what earlier writes left unsynced.
+            let after: Files = self.files()?;
+            for path in before.keys().chain(after.keys()) {
+                match after.get(path) {
+                    Some(file) if before.get(path) != Some(file) => {
+                        durable.insert(path.clone(), file.clone());
+                    },
+                    None if before.contains_key(path) => {
+                        durable.remove(path);
+                    },
+                    _ => {},
+                }
+            }
+        }
+        done
+    }
+
+    /// Fails a synced operation whose data already landed if it is the one to fail.
+    fn synced(&self, level: SyncLevel) -> io::Result<()> {
+        match level != SyncLevel::None && self.hits(Kind::Sync) {
+            true => Err(self.fault()),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Storage for FaultyStorage {
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.alive()?;
+        self.inner.read(key)
+    }
+
+    fn read_range(&self, key: &str, offset: u64, len: usize) -> io::Result<Option<Vec<u8>>> {
+        self.alive()?;
+        self.inner.read_range(key, offset, len)
+    }
+
+    fn write(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+        self.alive()?;
+        if self.on_files() {
+            return self.on_disk(level, true, || self.inner.write(key, bytes, level));
+        }
+        // A torn write only ever reaches the temp file, which is never renamed into place.
+        if self.hits(Kind::Write) || self.hits(Kind::Rename) {
+            return Err(self.fault());
+        }
+        self.inner.write(key, bytes, level)?;
+        self.synced(level)
+    }
+
+    fn append(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+        self.alive()?;
+        if self.on_files() {
+            return self.on_disk(level, true, || self.inner.append(key, bytes, level));
+        }
+        if self.hits(Kind::Write) {
+            if let Fault::TruncateWrite(n) = self.fault {
+                self.inner.append(key, &bytes[..n.min(bytes.len().saturating_sub(1))], level)?;
+            }
+            return Err(self.fault());
+        }
+        self.inner.append(key, bytes, level)?;
+        self.synced(level)
+    }
+
+    fn remove(&self, key: &str, level: SyncLevel) -> io::Result<()> {
+        self.alive()?;
+        if self.on_files() {
+            return self.on_disk(level, false, || self.inner.remove(key, level));
+        }
+        if self.hits(Kind::Rename) {
+            return Err(self.fault());
+        }
+        self.inner.remove(key, level)?;
+        self.synced(level)
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        self.alive()?;
+        self.inner.list()
+    }
+
+    fn lock(&self, exclusive: bool, mode: LockMode) -> io::Result<Option<LockGuard>> {
+        self.alive()?;
+        self.inner.lock(exclusive, mode)
+    }
+
+    fn fingerprint(&self, key: &str) -> io::Result<Option<u64>> {
+        self.alive()?;
+        self.inner.fingerprint(key)
+    }
+
+    fn move_aside(&self, key: &str, suffix: &str) -> io::Result<PathBuf> {
+        self.alive()?;
+        if self.on_files() {
+            return self.on_disk(SyncLevel::All, false, || self.inner.move_aside(key, suffix));
+        }
+        if self.hits(Kind::Rename) {
+            return Err(self.fault());
+        }
+        self.inner.move_aside(key, suffix)
+    }
+
+    fn copy_aside(&self, key: &str, suffix: &str) -> io::Result<Option<PathBuf>> {
+        self.alive()?;
+        if self.on_files() {
+            return self.on_disk(SyncLevel::All, false, || self.inner.copy_aside(key, suffix));
+        }
+        self.inner.copy_aside(key, suffix)
+    }
+
+    fn locate(&self, key: &str) -> PathBuf {
+        self.inner.locate(key)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.alive()?;
+        self.inner.sync()?;
+        if self.on_files() {
+            *self.durable.lock().unwrap_or_else(|e| e.into_inner()) = Some(self.files()?);
+        }
+        self.synced(SyncLevel::All)
+    }
+
+    fn usage(&self) -> io::Result<Usage> {
+        self.alive()?;
+        self.inner.usage()
+    }
+
+    fn compact(&self) -> io::Result<()> {
+        self.alive()?;
+        if self.on_files() {
+            return self.on_disk(SyncLevel::All, false, || self.inner.compact());
+        }
+        self.inner.compact()
+    }
+}
@@ -0,0 +1,69 @@
+use std::{collections::BTreeMap, io::{self, ErrorKind}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DataBase, MemoDoc, Op};
+
+/// The changes of a [`DataBase::transaction`] that aren't committed yet.
+///
+/// Every operation is checked like its [`DataBase`] counterpart, against the database as the
+/// operations before it in the transaction left it, and reads see those operations too. Nothing
+/// reaches the database until the transaction commits.
+pub struct Transaction<'a, T: Serialize + for<'de> Deserialize<'de> + MemoDoc + Clone> {
+    db: &'a DataBase<T>,
+    /// Every document changed so far, `None` if deleted.
+    changes: BTreeMap<String, Option<T>>,
+}
+
+impl<'a, T: Serialize + for<'de> Deserialize<'de> + MemoDoc + Clone> Transaction<'a, T> {
+    pub(crate) fn new(db: &'a DataBase<T>) -> Self {
+        Self { db, changes: BTreeMap::new() }
+    }
+
+    pub(crate) fn into_changes(self) -> BTreeMap<String, Option<T>> {
+        self.changes
+    }
+
+    /// Adds `data`, see [`DataBase::push`].
+    pub fn push(&mut self, data: T) -> io::Result<()> {
+        self.db.stage(&mut self.changes, Op::Push(data)).map(|_| ())
+    }
+
+    /// Replaces the document with the id of `data` and returns its previous value, see [`DataBase::replace`].
+    pub fn replace(&mut self, data: T) -> io::Result<T> {
+        self.db.stage(&mut self.changes, Op::Replace(data)).map(|previous| previous.expect("a successful replace returns the document"))
+    }
+
+    /// Adds or replaces `data` and returns the previous value if there was one, see [`DataBase::upsert`].
+    pub fn upsert(&mut self, data: T) -> io::Result<Option<T>> {
+        self.db.stage(&mut self.changes, Op::Upsert(data))
+    }
+
+    /// Changes the document stored under `id` in place and returns its previous value, see [`DataBase::update`].
+    pub fn update(&mut self, id: &str, f: impl FnOnce(&mut T)) -> io::Result<T> {
+        let Some(mut data) = self.db.staged(&self.changes, id)? else {
+            return Err(io::Error::new(ErrorKind::NotFound, format!("Data with specified ID ({id}) was not found.")));
+        };
+        f(&mut data);
+        if data.get_id() != id {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("update changed the ID ({id}) to ({})", data.get_id())));
+        }
+        self.replace(data)
+    }
+
+    /// Deletes the document stored under `id` and returns it, see [`DataBase::del`].
+    pub fn del(&mut self, id: &str) -> io::Result<T> {
+        self.db.stage(&mut self.changes, Op::Del(id.to_string())).map(|previous| previous.expect("a successful delete returns the document"))
+    }
+
+    /// Fetches the document stored under `id`, as changed by this transaction, see [`DataBase::get`].
+    pub fn get(&self, id: &str) -> Option<T> {
+        self.try_get(id).ok().flatten()
+    }
+
+    /// Fetches the document stored under `id`, as changed by this transaction, or the error of
+    /// reading it, see [`DataBase::try_get`].
+    pub fn try_get(&self, id: &str) -> io::Result<Option<T>> {
+        self.db.staged(&self.changes, id)
+    }
+}
@@ -0,0 +1,131 @@
+//! Bitcask behaviour that depends on segment files and background merges.
+
+use std::{fs, path::PathBuf, sync::Arc};
+
+use memorable::{BitcaskStorage, Config, ConflictPolicy, CorruptionPolicy, DataBase, MemoDoc, MemoError, Storage, StorageMode, SyncLevel};
+use memorable_macro_derive::MemoDoc;
+use serde::{Deserialize, Serialize};
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String,
+}
+
+fn temp(name: &str) -> PathBuf {
+    let dir: PathBuf = std::env::temp_dir().join(format!("memorable_bitcask_{name}_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn background_merges_are_no_conflict() {
+    pollster::block_on(async {
+        let dir: PathBuf = temp("merges");
+        let storage: BitcaskStorage = BitcaskStorage::new(&dir).segment_size(64).merge_after(2);
+        let config: Config = Config { mode: StorageMode::Bitcask, on_conflict: ConflictPolicy::Error, ..Config::default() };
+        let mut f: DataBase<Task> = DataBase::open_with_storage(Arc::new(storage), config.clone()).unwrap();
+        for i in 0..200 {
+            f.push(Task { uuid: format!("task-{i}") }).await.unwrap();
+            // Gives the merges started by the push time to swap in their output.
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let f: DataBase<Task> = DataBase::open_with(dir.to_str().unwrap(), config).unwrap();
+        assert_eq!(f.docs.len(), 200);
+        let _ = fs::remove_dir_all(&dir);
+    });
+}
+
+#[test]
+fn failed_rollover_fails_the_next_write() {
+    let dir: PathBuf = temp("rollover");
+    let storage: BitcaskStorage = BitcaskStorage::new(&dir).segment_size(1);
+    storage.write("a", b"1", SyncLevel::All).unwrap();
+    // Keeps the next segment from being created, like a full disk would.
+    let blocker: PathBuf = dir.join("000003.data");
+    fs::create_dir(&blocker).unwrap();
+    assert!(storage.write("b", b"2", SyncLevel::All).is_err());
+    fs::remove_dir(&blocker).unwrap();
+
+    let reopened: BitcaskStorage = BitcaskStorage::new(&dir);
+    assert_eq!(reopened.read("a").unwrap().as_deref(), Some(&b"1"[..]));
+    assert_eq!(reopened.read("b").unwrap(), None);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+/// The active segment, the one with the highest id.
+fn active_segment(dir: &PathBuf) -> PathBuf {
+    fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "data"))
+        .max()
+        .unwrap()
+}
+
+#[test]
+fn torn_active_tail_is_cut() {
+    pollster::block_on(async {
+        let config: Config = Config { mode: StorageMode::Bitcask, ..Config::default() };
+        // Zeroes as left by a power cut, a header that passes for a short record, and random bytes.
+        let tails: [&[u8]; 3] = [&[0; 40], &[7, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, b'x', b'y'], b"\x91garbage\x00\x00\x00 bytes"];
+        for (i, tail) in tails.into_iter().enumerate() {
+            let dir: PathBuf = temp(&format!("torn_{i}"));
+            let mut f: DataBase<Task> = DataBase::open_with(dir.to_str().unwrap(), config.clone()).unwrap();
+            f.push(Task { uuid: "a".into() }).await.unwrap();
+            drop(f);
+            let segment: PathBuf = active_segment(&dir);
+            let len: u64 = fs::metadata(&segment).unwrap().len();
+            let mut bytes: Vec<u8> = fs::read(&segment).unwrap();
+            bytes.extend_from_slice(tail);
+            fs::write(&segment, bytes).unwrap();
+
+            let mut f: DataBase<Task> = DataBase::open_with(dir.to_str().unwrap(), config.clone()).unwrap();
+            assert!(f.docs.contains_key("a"), "tail {i}");
+            f.push(Task { uuid: "b".into() }).await.unwrap();
+            assert!(fs::metadata(&segment).unwrap().len() > len);
+            let f: DataBase<Task> = DataBase::open_with(dir.to_str().unwrap(), config.clone()).unwrap();
+            assert!(f.docs.contains_key("a") && f.docs.contains_key("b"), "tail {i}");
+            let _ = fs::remove_dir_all(&dir);
+        }
+    });
+}
+
+#[test]
+fn damaged_segment_goes_through_the_corruption_policy() {
+    pollster::block_on(async {
+        let dir: PathBuf = temp("damaged");
+        let config: Config = Config { mode: StorageMode::Bitcask, ..Config::default() };
+        let mut f: DataBase<Task> = DataBase::open_with(dir.to_str().unwrap(), config.clone()).unwrap();
+        f.push(Task { uuid: "a".into() }).await.unwrap();
+        f.push(Task { uuid: "b".into() }).await.unwrap();
+        drop(f);
+        // Flips a byte of the first record, which an intact one follows.
+        let segment: PathBuf = active_segment(&dir);
+        let mut bytes: Vec<u8> = fs::read(&segment).unwrap();
+        bytes[14] ^= 1;
+        fs::write(&segment, &bytes).unwrap();
+
+        let err = DataBase::<Task>::open_with(dir.to_str().unwrap(), config.clone()).unwrap_err();
+        assert!(matches!(MemoError::from_io(&err), Some(MemoError::Corrupted { .. })), "{err}");
+        assert_eq!(fs::read(&segment).unwrap(), bytes);
+
+        let config: Config = Config { on_corruption: CorruptionPolicy::Backup, ..config };
+        let f: DataBase<Task> = DataBase::open_with(dir.to_str().unwrap(), config).unwrap();
+        assert!(f.docs.is_empty());
+        assert_eq!(fs::read(&f.recovered()[0]).unwrap(), bytes);
+        let _ = fs::remove_dir_all(&dir);
+    });
+}
+
+#[test]
+fn interrupted_merge_output_is_removed() {
+    let dir: PathBuf = temp("merging");
+    let storage: BitcaskStorage = BitcaskStorage::new(&dir);
+    storage.write("a", b"1", SyncLevel::All).unwrap();
+    let leftover: PathBuf = dir.join("000000.data.merging");
+    fs::write(&leftover, b"half a merge").unwrap();
+
+    let reopened: BitcaskStorage = BitcaskStorage::new(&dir);
+    assert_eq!(reopened.read("a").unwrap().as_deref(), Some(&b"1"[..]));
+    assert!(!leftover.exists());
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,76 @@
+//! Every codec round-trips a document using everything json can hold.
+
+use std::{collections::{BTreeMap, HashMap}, fs, path::PathBuf, sync::Arc};
+
+use memorable::{Codec, Config, DataBase, JsonCompact, JsonPretty, MemoDoc};
+use memorable_macro_derive::MemoDoc;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum Status {
+    Open,
+    Blocked { by: String },
+    Done(u32),
+}
+
+#[derive(MemoDoc, Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Task {
+    uuid: String,
+    big: u64,
+    small: i64,
+    weight: f64,
+    bytes: Vec<u8>,
+    parent: Option<String>,
+    status: Vec<Status>,
+    /// Integer keys are stored as strings and parsed back.
+    scores: BTreeMap<u32, i8>,
+}
+
+impl Default for Task {
+    fn default() -> Self {
+        Task {
+            uuid: "a".into(),
+            big: u64::MAX,
+            small: i64::MIN,
+            weight: -0.1,
+            bytes: vec![0, 255, 10],
+            parent: None,
+            status: vec![Status::Open, Status::Blocked { by: "b".into() }, Status::Done(3)],
+            scores: BTreeMap::from([(1, -1), (u32::MAX, 127)]),
+        }
+    }
+}
+
+fn codecs() -> HashMap<&'static str, Arc<dyn Codec>> {
+    #[allow(unused_mut)]
+    let mut codecs: HashMap<&'static str, Arc<dyn Codec>> = HashMap::from([
+        ("pretty", Arc::new(JsonPretty) as Arc<dyn Codec>),
+        ("compact", Arc::new(JsonCompact) as Arc<dyn Codec>),
+    ]);
+    #[cfg(feature = "msgpack")]
+    codecs.insert("msgpack", Arc::new(memorable::MessagePack));
+    #[cfg(feature = "cbor")]
+    codecs.insert("cbor", Arc::new(memorable::Cbor));
+    #[cfg(feature = "bincode")]
+    codecs.insert("bincode", Arc::new(memorable::Bincode));
+    #[cfg(feature = "ron")]
+    codecs.insert("ron", Arc::new(memorable::Ron));
+    codecs
+}
+
+#[test]
+fn round_trip() {
+    pollster::block_on(async {
+        for (name, codec) in codecs() {
+            let path: PathBuf = std::env::temp_dir().join(format!("memorable_codecs_{name}_{}", std::process::id()));
+            let file: &str = path.to_str().unwrap();
+            let _ = fs::remove_file(&path);
+            let config: Config = Config { codec, ..Config::default() };
+            let mut f: DataBase<Task> = DataBase::open_with(file, config.clone()).unwrap();
+            f.push(Task::default()).await.unwrap();
+            let f: DataBase<Task> = DataBase::open_with(file, config).unwrap();
+            assert_eq!(f.docs["a"], Task::default(), "{name}");
+            let _ = fs::remove_file(&path);
+        }
+    });
+}
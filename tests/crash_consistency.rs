@@ -0,0 +1,356 @@
+//! Runs a workload against storage that fails at every possible point, then reopens the database
+//! and checks that no acknowledged write was lost and no acknowledged delete undone.
+
+use std::{collections::{BTreeMap, BTreeSet}, fs, path::{Path, PathBuf}, sync::Arc};
+
+use memorable::{AutoCompact, BatchMode, BitcaskStorage, Config, DataBase, DirStorage, Durability, FileStorage, MemoDoc, Storage, StorageMode};
+use memorable::testing::{Fault, FaultyStorage};
+use memorable_macro_derive::MemoDoc;
+use serde::{Deserialize, Serialize};
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+struct Task {
+    uuid: String,
+    n: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Push(&'static str, u32),
+    Update(&'static str, u32),
+    Replace(&'static str, u32),
+    Upsert(&'static str, u32),
+    Del(&'static str),
+    PushMany(&'static [(&'static str, u32)]),
+    DelMany(&'static [&'static str]),
+    Compact,
+    Sync,
+}
+
+const WORKLOAD: [Op; 19] = [
+    Op::Push("a", 1),
+    Op::Push("b", 2),
+    Op::Del("a"),
+    Op::Push("c", 3),
+    Op::Sync,
+    Op::Compact,
+    Op::Push("a", 4),
+    Op::Del("b"),
+    Op::Push("d", 5),
+    Op::Update("d", 6),
+    Op::Push("b", 7),
+    Op::Upsert("a", 8),
+    Op::PushMany(&[("f", 11), ("g", 12)]),
+    Op::Compact,
+    Op::DelMany(&["f", "d"]),
+    Op::Sync,
+    Op::Replace("c", 9),
+    Op::Del("c"),
+    Op::Upsert("e", 10),
+];
+
+const IDS: [&str; 7] = ["a", "b", "c", "d", "e", "f", "g"];
+
+const MODES: [StorageMode; 5] = [
+    StorageMode::Snapshot,
+    StorageMode::Journal,
+    StorageMode::Lines,
+    StorageMode::Directory,
+    StorageMode::Bitcask,
+];
+
+const FAULTS: [Fault; 8] = [
+    Fault::FailWrite,
+    Fault::TruncateWrite(0),
+    Fault::TruncateWrite(7),
+    Fault::FailSync,
+    Fault::FailRename,
+    Fault::TornWrite(0),
+    Fault::TornWrite(7),
+    Fault::TornWrite(40),
+];
+
+fn config(mode: StorageMode) -> Config {
+    Config {
+        mode,
+        // Folds the journal every few records, so faults also hit the middle of a fold.
+        journal_limit: 120,
+        auto_compact: AutoCompact::Garbage { ratio: 0.3, min_size: 0 },
+        ..Config::default()
+    }
+}
+
+async fn apply(db: &mut DataBase<Task>, op: Op) -> std::io::Result<()> {
+    match op {
+        Op::Push(id, n) => db.push(Task { uuid: id.to_string(), n }).await,
+        Op::Update(id, n) => db.update(id, |doc| doc.n = n).await.map(|_| ()),
+        Op::Replace(id, n) => db.replace(Task { uuid: id.to_string(), n }).await.map(|_| ()),
+        Op::Upsert(id, n) => db.upsert(Task { uuid: id.to_string(), n }).await.map(|_| ()),
+        Op::Del(id) => db.del(id).await.map(|_| ()),
+        Op::PushMany(docs) => {
+            let docs: Vec<Task> = docs.iter().map(|(id, n)| Task { uuid: id.to_string(), n: *n }).collect();
+            db.push_many(docs, BatchMode::AllOrNothing).await?.into_iter().collect()
+        },
+        Op::DelMany(ids) => db.del_many(ids.iter().copied(), BatchMode::AllOrNothing).await?.into_iter().try_for_each(|r| r.map(|_| ())),
+        Op::Compact => db.compact().map(|_| ()),
+        Op::Sync => db.sync(),
+    }
+}
+
+/// Changes `docs` as `op` does when it succeeds.
+fn record(docs: &mut BTreeMap<&'static str, u32>, op: Op) {
+    match op {
+        Op::Push(id, n) | Op::Update(id, n) | Op::Replace(id, n) | Op::Upsert(id, n) => {
+            docs.insert(id, n);
+        },
+        Op::Del(id) => {
+            docs.remove(id);
+        },
+        Op::PushMany(pushed) => docs.extend(pushed.iter().copied()),
+        Op::DelMany(ids) => docs.retain(|id, _| !ids.contains(id)),
+        Op::Compact | Op::Sync => {},
+    }
+}
+
+/// Runs the workload on `storage` and returns the acknowledged documents with the ids of the
+/// operations that failed, whose outcome is unknown. Stops at the first failure if `crash`.
+async fn run(storage: Arc<dyn Storage>, mode: StorageMode, crash: bool) -> (BTreeMap<&'static str, u32>, BTreeSet<&'static str>) {
+    let mut acked: BTreeMap<&'static str, u32> = BTreeMap::new();
+    let mut unknown: BTreeSet<&'static str> = BTreeSet::new();
+    let Ok(mut db) = DataBase::<Task>::open_with_storage(storage, config(mode)) else {
+        return (acked, unknown);
+    };
+    for op in WORKLOAD {
+        match (apply(&mut db, op).await, op) {
+            (Ok(()), op) => record(&mut acked, op),
+            (Err(_), op) => {
+                match op {
+                    Op::Push(id, _) | Op::Update(id, _) | Op::Replace(id, _) | Op::Upsert(id, _) | Op::Del(id) => {
+                        unknown.insert(id);
+                    },
+                    Op::PushMany(docs) => unknown.extend(docs.iter().map(|(id, _)| *id)),
+                    Op::DelMany(ids) => unknown.extend(ids.iter().copied()),
+                    Op::Compact | Op::Sync => {},
+                }
+                if crash {
+                    break;
+                }
+            }
+        }
+    }
+    (acked, unknown)
+}
+
+/// Reopens the database on `storage` and checks it against what the workload acknowledged.
+async fn verify(storage: Arc<dyn Storage>, mode: StorageMode, acked: &BTreeMap<&str, u32>, unknown: &BTreeSet<&str>, case: &str) {
+    let db: DataBase<Task> = DataBase::open_with_storage(storage, config(mode))
+        .unwrap_or_else(|e| panic!("{case}: reopening failed: {e}"));
+    for id in IDS.iter().filter(|id| !unknown.contains(*id)) {
+        let n: Option<u32> = db.get(id).await.map(|doc| doc.n);
+        assert_eq!(n, acked.get(id).copied(), "{case}: document {id}");
+    }
+}
+
+/// A storage of the kind `mode` is opened with, on the files at `path`.
+fn backing(mode: StorageMode, path: &Path) -> Arc<dyn Storage> {
+    match mode {
+        StorageMode::Snapshot | StorageMode::Journal | StorageMode::Lines => Arc::new(FileStorage::new(path)),
+        StorageMode::Directory => Arc::new(DirStorage::new(path)),
+        StorageMode::Bitcask => Arc::new(BitcaskStorage::new(path)),
+    }
+}
+
+async fn inject(crash: bool) {
+    let mut fired: usize = 0;
+    for mode in MODES {
+        for fault in FAULTS {
+            for at in 1.. {
+                let case: String = format!("{mode:?}, {fault:?} at {at}, crash: {crash}");
+                // Every case gets its own directory, the database is `db` in it.
+                let dir: PathBuf = temp(&format!("{mode:?}_{fault:?}_{at}_{crash}"));
+                fs::create_dir_all(&dir).unwrap();
+                let path: PathBuf = dir.join("db");
+                let inner: Arc<dyn Storage> = backing(mode, &path);
+                let mut faulty: FaultyStorage = FaultyStorage::new(inner.clone(), fault, at);
+                if crash {
+                    faulty = faulty.crash();
+                }
+                let faulty: Arc<FaultyStorage> = Arc::new(faulty);
+                let (acked, unknown) = run(faulty.clone(), mode, crash).await;
+                if !faulty.fired() {
+                    // The workload finished before reaching the `at`th operation.
+                    let _ = fs::remove_dir_all(&dir);
+                    break;
+                }
+                fired += 1;
+                // A fresh storage reads the files as a restarted process would, not the state the
+                // faulted one kept in memory.
+                verify(backing(mode, &path), mode, &acked, &unknown, &case).await;
+                let _ = fs::remove_dir_all(&dir);
+            }
+        }
+    }
+    assert!(fired > 100, "only {fired} faults were injected");
+}
+
+#[test]
+fn crash_after_fault() {
+    pollster::block_on(inject(true));
+}
+
+#[test]
+fn continue_after_fault() {
+    pollster::block_on(inject(false));
+}
+
+/// Runs the workload on `storage` until an operation fails, and returns the documents after every
+/// operation since the last sync: what losing the unsynced writes may leave.
+async fn history(storage: Arc<dyn Storage>, config: Config) -> Vec<BTreeMap<&'static str, u32>> {
+    let mut states: Vec<BTreeMap<&'static str, u32>> = vec![BTreeMap::new()];
+    let Ok(mut db) = DataBase::<Task>::open_with_storage(storage, config) else {
+        return states;
+    };
+    for op in WORKLOAD {
+        if apply(&mut db, op).await.is_err() {
+            break;
+        }
+        let mut docs: BTreeMap<&'static str, u32> = states[states.len() - 1].clone();
+        record(&mut docs, op);
+        if let Op::Sync = op {
+            states.clear();
+        }
+        states.push(docs);
+    }
+    states
+}
+
+#[test]
+fn power_loss() {
+    pollster::block_on(async {
+        let mut fired: usize = 0;
+        for mode in [StorageMode::Journal, StorageMode::Lines, StorageMode::Bitcask] {
+            // Nothing is synced but what `sync` and the bitcask merge force.
+            let config: Config = Config { durability: Durability::None, ..config(mode) };
+            for at in 1.. {
+                let case: String = format!("{mode:?}, power lost at write {at}");
+                let dir: PathBuf = temp(&format!("power_{mode:?}_{at}"));
+                fs::create_dir_all(&dir).unwrap();
+                let path: PathBuf = dir.join("db");
+                let faulty: Arc<FaultyStorage> = Arc::new(FaultyStorage::new(backing(mode, &path), Fault::LoseUnsynced, at));
+                let states: Vec<BTreeMap<&str, u32>> = history(faulty.clone(), config.clone()).await;
+                if !faulty.fired() {
+                    let _ = fs::remove_dir_all(&dir);
+                    break;
+                }
+                fired += 1;
+                let db: DataBase<Task> = DataBase::open_with_storage(backing(mode, &path), config.clone())
+                    .unwrap_or_else(|e| panic!("{case}: reopening failed: {e}"));
+                let mut docs: BTreeMap<&str, u32> = BTreeMap::new();
+                for id in IDS {
+                    if let Some(doc) = db.get(id).await {
+                        docs.insert(id, doc.n);
+                    }
+                }
+                assert!(states.contains(&docs), "{case}: {docs:?} is none of {states:?}");
+                let _ = fs::remove_dir_all(&dir);
+            }
+        }
+        assert!(fired > 30, "only {fired} power losses were simulated");
+    });
+}
+
+fn temp(name: &str) -> PathBuf {
+    let path: PathBuf = std::env::temp_dir().join(format!("memorable_crash_{name}_{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_dir_all(&path);
+    path
+}
+
+/// Cuts the file at `path` at every length its last `tail` bytes span, reopening the database each time.
+async fn cut_tail(path: &PathBuf, tail: usize, reopen: impl Fn() -> DataBase<Task>) {
+    let bytes: Vec<u8> = fs::read(path).unwrap();
+    for len in bytes.len() - tail..bytes.len() {
+        fs::write(path, &bytes[..len]).unwrap();
+        let db: DataBase<Task> = reopen();
+        assert_eq!(db.get("a").await.map(|doc| doc.n), Some(1), "cut at {len}");
+        assert!(db.get("b").await.is_none(), "cut at {len}");
+    }
+}
+
+#[test]
+fn torn_log_tail() {
+    pollster::block_on(async {
+        for mode in [StorageMode::Journal, StorageMode::Lines] {
+            let path: PathBuf = temp(&format!("{mode:?}"));
+            let file: &str = path.to_str().unwrap();
+            let config: Config = Config { mode, journal_limit: u64::MAX, ..Config::default() };
+            let mut db: DataBase<Task> = DataBase::open_with(file, config.clone()).unwrap();
+            db.push(Task { uuid: "a".into(), n: 1 }).await.unwrap();
+            let log: PathBuf = match mode {
+                StorageMode::Journal => PathBuf::from(format!("{file}.journal")),
+                _ => path.clone(),
+            };
+            let before: usize = fs::read(&log).unwrap().len();
+            db.push(Task { uuid: "b".into(), n: 2 }).await.unwrap();
+            drop(db);
+            let tail: usize = fs::read(&log).unwrap().len() - before;
+            cut_tail(&log, tail, || DataBase::open_with(file, config.clone()).unwrap()).await;
+            let _ = fs::remove_file(&log);
+            let _ = fs::remove_file(&path);
+        }
+    });
+}
+
+#[test]
+fn torn_batch() {
+    pollster::block_on(async {
+        for mode in [StorageMode::Journal, StorageMode::Lines] {
+            let path: PathBuf = temp(&format!("batch_{mode:?}"));
+            let file: &str = path.to_str().unwrap();
+            let config: Config = Config { mode, journal_limit: u64::MAX, ..Config::default() };
+            let mut db: DataBase<Task> = DataBase::open_with(file, config.clone()).unwrap();
+            db.push(Task { uuid: "a".into(), n: 1 }).await.unwrap();
+            let log: PathBuf = match mode {
+                StorageMode::Journal => PathBuf::from(format!("{file}.journal")),
+                _ => path.clone(),
+            };
+            let before: usize = fs::read(&log).unwrap().len();
+            let batch: Vec<Task> = ["b", "c", "d"].into_iter().map(|id| Task { uuid: id.into(), n: 2 }).collect();
+            db.push_many(batch, BatchMode::AllOrNothing).await.unwrap();
+            drop(db);
+            let bytes: Vec<u8> = fs::read(&log).unwrap();
+            for len in before..bytes.len() {
+                fs::write(&log, &bytes[..len]).unwrap();
+                let db: DataBase<Task> = DataBase::open_with(file, config.clone()).unwrap();
+                assert_eq!(db.get("a").await.map(|doc| doc.n), Some(1), "{mode:?} cut at {len}");
+                for id in ["b", "c", "d"] {
+                    assert!(db.get(id).await.is_none(), "{mode:?} cut at {len}: {id} of a torn batch");
+                }
+            }
+            let _ = fs::remove_file(&log);
+            let _ = fs::remove_file(&path);
+        }
+    });
+}
+
+#[test]
+fn torn_segment_tail() {
+    pollster::block_on(async {
+        let dir: PathBuf = temp("bitcask");
+        let config: Config = Config { mode: StorageMode::Bitcask, ..Config::default() };
+        let open = || DataBase::open_with_storage(Arc::new(BitcaskStorage::new(&dir)), config.clone()).unwrap();
+        let mut db: DataBase<Task> = open();
+        db.push(Task { uuid: "a".into(), n: 1 }).await.unwrap();
+        let segment: PathBuf = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "data"))
+            .max()
+            .unwrap();
+        let before: usize = fs::read(&segment).unwrap().len();
+        db.push(Task { uuid: "b".into(), n: 2 }).await.unwrap();
+        drop(db);
+        let tail: usize = fs::read(&segment).unwrap().len() - before;
+        cut_tail(&segment, tail, open).await;
+        let _ = fs::remove_dir_all(&dir);
+    });
+}
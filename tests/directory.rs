@@ -0,0 +1,37 @@
+//! File names of the documents of a directory database.
+
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use memorable::{Config, DataBase, MemoDoc, StorageMode};
+use memorable_macro_derive::MemoDoc;
+use serde::{Deserialize, Serialize};
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String,
+}
+
+#[test]
+fn long_ids() {
+    pollster::block_on(async {
+        let dir: PathBuf = std::env::temp_dir().join(format!("memorable_directory_long_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config: Config = Config { mode: StorageMode::Directory, ..Config::default() };
+        let mut f: DataBase<Task> = DataBase::open_with(dir.to_str().unwrap(), config.clone()).unwrap();
+
+        // Kept as they are, uppercase included.
+        let kept: String = "Ab".repeat(106);
+        f.push(Task { uuid: kept.clone() }).await.unwrap();
+        assert!(dir.join(format!("{kept}.json")).exists());
+
+        // Every byte is escaped into three, too long for a file name.
+        let escaped: String = "/".repeat(85);
+        let err = f.push(Task { uuid: escaped.clone() }).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "{err}");
+        assert!(!f.docs.contains_key(&escaped));
+
+        let f: DataBase<Task> = DataBase::open_with(dir.to_str().unwrap(), config).unwrap();
+        assert_eq!(f.ids().unwrap(), [kept]);
+        let _ = fs::remove_dir_all(&dir);
+    });
+}
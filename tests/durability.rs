@@ -0,0 +1,89 @@
+//! Checks which `SyncLevel` every write receives under each `Durability`.
+
+use std::{io, path::PathBuf, sync::{Arc, Mutex}, time::Duration};
+
+use memorable::{Config, DataBase, Durability, LockGuard, LockMode, MemoDoc, MemoryStorage, Storage, StorageMode, SyncLevel};
+use memorable_macro_derive::MemoDoc;
+use serde::{Deserialize, Serialize};
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String,
+}
+
+/// Records the level of every write, append and removal made to a memory storage.
+#[derive(Debug, Default)]
+struct Recorded(MemoryStorage, Mutex<Vec<SyncLevel>>);
+
+impl Recorded {
+    fn take(&self) -> Vec<SyncLevel> {
+        std::mem::take(&mut *self.1.lock().unwrap())
+    }
+}
+
+impl Storage for Recorded {
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> { self.0.read(key) }
+    fn write(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+        self.1.lock().unwrap().push(level);
+        self.0.write(key, bytes, level)
+    }
+    fn append(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> {
+        self.1.lock().unwrap().push(level);
+        self.0.append(key, bytes, level)
+    }
+    fn remove(&self, key: &str, level: SyncLevel) -> io::Result<()> {
+        self.1.lock().unwrap().push(level);
+        self.0.remove(key, level)
+    }
+    fn list(&self) -> io::Result<Vec<String>> { self.0.list() }
+    fn lock(&self, exclusive: bool, mode: LockMode) -> io::Result<Option<LockGuard>> { self.0.lock(exclusive, mode) }
+    fn fingerprint(&self, key: &str) -> io::Result<Option<u64>> { self.0.fingerprint(key) }
+    fn move_aside(&self, key: &str, suffix: &str) -> io::Result<PathBuf> { self.0.move_aside(key, suffix) }
+    fn copy_aside(&self, key: &str, suffix: &str) -> io::Result<Option<PathBuf>> { self.0.copy_aside(key, suffix) }
+    fn locate(&self, key: &str) -> PathBuf { self.0.locate(key) }
+    fn sync(&self) -> io::Result<()> { self.0.sync() }
+}
+
+/// Opens a database with `durability`, pushes two documents and deletes one, and returns the
+/// levels of the writes made by `open` and by the three calls.
+fn levels(mode: StorageMode, durability: Durability) -> (Vec<SyncLevel>, Vec<SyncLevel>) {
+    pollster::block_on(async {
+        let storage: Arc<Recorded> = Arc::new(Recorded::default());
+        let config: Config = Config { mode, durability, journal_limit: u64::MAX, ..Config::default() };
+        let mut f: DataBase<Task> = DataBase::open_with_storage(storage.clone(), config).unwrap();
+        let opened: Vec<SyncLevel> = storage.take();
+        f.push(Task { uuid: "a".into() }).await.unwrap();
+        f.push(Task { uuid: "b".into() }).await.unwrap();
+        f.del("a").await.unwrap();
+        (opened, storage.take())
+    })
+}
+
+#[test]
+fn every_level() {
+    let hour: Duration = Duration::from_secs(3600);
+    for mode in [StorageMode::Snapshot, StorageMode::Journal, StorageMode::Lines, StorageMode::Directory] {
+        for (durability, level) in [
+            (Durability::None, SyncLevel::None),
+            (Durability::Flush, SyncLevel::Data),
+            (Durability::Fsync, SyncLevel::All),
+            // Every write is due for a sync when the interval is zero.
+            (Durability::FsyncInterval(Duration::ZERO), SyncLevel::All),
+        ] {
+            let (opened, written) = levels(mode, durability);
+            assert!(opened.iter().all(|l| *l == level), "{mode:?}, {durability:?}: open wrote {opened:?}");
+            assert_eq!(written, [level; 3], "{mode:?}, {durability:?}");
+        }
+
+        // The first write syncs fully, the rest of the interval leaves flushing to the OS.
+        let (opened, written) = levels(mode, Durability::FsyncInterval(hour));
+        match opened.split_first() {
+            Some((first, rest)) => {
+                assert_eq!(*first, SyncLevel::All, "{mode:?}");
+                assert!(rest.iter().all(|l| *l == SyncLevel::None), "{mode:?}: open wrote {opened:?}");
+                assert_eq!(written, [SyncLevel::None; 3], "{mode:?}");
+            },
+            None => assert_eq!(written, [SyncLevel::All, SyncLevel::None, SyncLevel::None], "{mode:?}"),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Lazy databases reading documents another handle changed underneath them.
+
+use std::{fs, io::{self, Write}, path::PathBuf, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
+
+use memorable::{Config, DataBase, FileStorage, LockGuard, LockMode, MemoDoc, MemoError, ReadSeek, Storage, StorageMode, SyncLevel, Usage};
+use memorable_macro_derive::MemoDoc;
+use serde::{Deserialize, Serialize};
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String,
+}
+
+/// Counts the reads of whole objects and of ranges made from a file storage.
+#[derive(Debug)]
+struct Counted(FileStorage, AtomicUsize);
+
+impl Storage for Counted {
+    fn read(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        self.0.read(key)
+    }
+    fn read_range(&self, key: &str, offset: u64, len: usize) -> io::Result<Option<Vec<u8>>> {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        self.0.read_range(key, offset, len)
+    }
+    fn reader(&self, key: &str) -> io::Result<Option<Box<dyn ReadSeek>>> { self.0.reader(key) }
+    fn write(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> { self.0.write(key, bytes, level) }
+    fn write_with(&self, key: &str, level: SyncLevel, fill: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+        self.0.write_with(key, level, fill)
+    }
+    fn append(&self, key: &str, bytes: &[u8], level: SyncLevel) -> io::Result<()> { self.0.append(key, bytes, level) }
+    fn remove(&self, key: &str, level: SyncLevel) -> io::Result<()> { self.0.remove(key, level) }
+    fn list(&self) -> io::Result<Vec<String>> { self.0.list() }
+    fn lock(&self, exclusive: bool, mode: LockMode) -> io::Result<Option<LockGuard>> { self.0.lock(exclusive, mode) }
+    fn fingerprint(&self, key: &str) -> io::Result<Option<u64>> { self.0.fingerprint(key) }
+    fn move_aside(&self, key: &str, suffix: &str) -> io::Result<PathBuf> { self.0.move_aside(key, suffix) }
+    fn copy_aside(&self, key: &str, suffix: &str) -> io::Result<Option<PathBuf>> { self.0.copy_aside(key, suffix) }
+    fn locate(&self, key: &str) -> PathBuf { self.0.locate(key) }
+    fn sync(&self) -> io::Result<()> { self.0.sync() }
+    fn usage(&self) -> io::Result<Usage> { self.0.usage() }
+}
+
+fn temp(name: &str) -> PathBuf {
+    let path: PathBuf = std::env::temp_dir().join(format!("memorable_lazy_{name}_{}.jsonl", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn writes_stay_out_of_docs() {
+    pollster::block_on(async {
+        for mode in [StorageMode::Lines, StorageMode::Directory, StorageMode::Bitcask] {
+            let path: PathBuf = temp(&format!("{mode:?}"));
+            let path: &str = path.to_str().unwrap();
+            let _ = fs::remove_dir_all(path);
+            let config: Config = Config { mode, lazy: true, cache: 2, ..Config::default() };
+            let mut f: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+            for i in 0..10 {
+                f.push(Task { uuid: format!("{i}") }).await.unwrap();
+            }
+            f.transaction(|tx| {
+                tx.del("0")?;
+                tx.upsert(Task { uuid: "10".into() })
+            })
+            .await
+            .unwrap();
+            assert!(f.docs.is_empty(), "{mode:?}");
+            assert_eq!(f.ids().unwrap().len(), 10, "{mode:?}");
+            assert_eq!(f.try_get("3").await.unwrap().unwrap().uuid, "3", "{mode:?}");
+            assert!(f.try_get("0").await.unwrap().is_none(), "{mode:?}");
+
+            let f: DataBase<Task> = DataBase::open_with(path, config).unwrap();
+            assert_eq!(f.try_get("10").await.unwrap().unwrap().uuid, "10", "{mode:?}");
+            let _ = fs::remove_file(path);
+            let _ = fs::remove_dir_all(path);
+        }
+    });
+}
+
+#[test]
+fn compacted_lines_are_no_other_document() {
+    pollster::block_on(async {
+        let path: PathBuf = temp("compacted");
+        let path: &str = path.to_str().unwrap();
+        let config: Config = Config { mode: StorageMode::Lines, lazy: true, ..Config::default() };
+        let mut writer: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+        writer.push(Task { uuid: "a".into() }).await.unwrap();
+        writer.push(Task { uuid: "b".into() }).await.unwrap();
+
+        let reader: DataBase<Task> = DataBase::open_with(path, config.clone()).unwrap();
+        writer.del("a").await.unwrap();
+        writer.compact().unwrap();
+        // `b` is rewritten at the offset `a` was indexed at.
+        let err: io::Error = reader.try_get("a").await.unwrap_err();
+        assert!(matches!(err.get_ref().and_then(|e| e.downcast_ref()), Some(MemoError::Conflict { .. })), "{err}");
+        assert!(reader.get("a").await.is_none());
+
+        let reader: DataBase<Task> = DataBase::open_with(path, config).unwrap();
+        assert!(reader.try_get("a").await.unwrap().is_none());
+        assert_eq!(reader.try_get("b").await.unwrap().unwrap().uuid, "b");
+        let _ = fs::remove_file(path);
+    });
+}
+
+#[test]
+fn single_file_modes_refuse_lazy() {
+    for mode in [StorageMode::Snapshot, StorageMode::Journal] {
+        let path: PathBuf = temp(&format!("refused_{mode:?}"));
+        let config: Config = Config { mode, lazy: true, ..Config::default() };
+        let err: io::Error = DataBase::<Task>::open_with(path.to_str().unwrap(), config).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{mode:?}: {err}");
+        assert!(!path.exists(), "{mode:?}");
+    }
+}
+
+#[test]
+fn compaction_streams_the_unread_lines() {
+    pollster::block_on(async {
+        let path: PathBuf = temp("streamed");
+        let config: Config = Config { mode: StorageMode::Lines, lazy: true, ..Config::default() };
+        let mut f: DataBase<Task> = DataBase::open_with(path.to_str().unwrap(), config.clone()).unwrap();
+        for i in 0..100 {
+            f.push(Task { uuid: format!("{i:03}") }).await.unwrap();
+        }
+        f.del("042").await.unwrap();
+
+        let storage: Arc<Counted> = Arc::new(Counted(FileStorage::new(&path), AtomicUsize::new(0)));
+        let mut f: DataBase<Task> = DataBase::open_with_storage(storage.clone(), config.clone()).unwrap();
+        storage.1.store(0, Ordering::SeqCst);
+        let compaction = f.compact().unwrap();
+        assert!(compaction.reclaimed() > 0);
+        assert_eq!(storage.1.load(Ordering::SeqCst), 0);
+
+        let f: DataBase<Task> = DataBase::open_with(path.to_str().unwrap(), config).unwrap();
+        assert_eq!(f.ids().unwrap().len(), 99);
+        assert_eq!(f.try_get("099").await.unwrap().unwrap().uuid, "099");
+        let _ = fs::remove_file(path);
+    });
+}
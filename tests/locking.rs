@@ -0,0 +1,44 @@
+//! What opening a database may do to its files while it only holds the shared lock.
+
+use std::{fs, path::PathBuf};
+
+use memorable::{Config, DataBase, MemoDoc, StorageMode};
+use memorable_macro_derive::MemoDoc;
+use serde::{Deserialize, Serialize};
+
+#[derive(MemoDoc, Serialize, Deserialize, Default, Clone, Debug)]
+struct Task {
+    uuid: String,
+}
+
+#[test]
+fn shared_open_leaves_torn_journal_alone() {
+    pollster::block_on(async {
+        let path: PathBuf = std::env::temp_dir().join(format!("memorable_locking_torn_{}.json", std::process::id()));
+        let file: &str = path.to_str().unwrap();
+        let journal: PathBuf = PathBuf::from(format!("{file}.journal"));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&journal);
+        let config: Config = Config { mode: StorageMode::Journal, ..Config::default() };
+        let mut f: DataBase<Task> = DataBase::open_with(file, config.clone()).unwrap();
+        f.push(Task { uuid: "a".into() }).await.unwrap();
+        drop(f);
+
+        // The remains of an append interrupted by a crash.
+        let mut torn: Vec<u8> = fs::read(&journal).unwrap();
+        torn.extend_from_slice(br#"{"op":"put","id":"b","doc":{"uu"#);
+        fs::write(&journal, &torn).unwrap();
+        // Opening only needs the shared lock, which must not write.
+        let mut f: DataBase<Task> = DataBase::open_with(file, config.clone()).unwrap();
+        assert_eq!(fs::read(&journal).unwrap(), torn);
+        assert!(f.docs.contains_key("a") && !f.docs.contains_key("b"));
+
+        // The next write holds the exclusive lock and cuts the tail off first.
+        f.push(Task { uuid: "c".into() }).await.unwrap();
+        let f: DataBase<Task> = DataBase::open_with(file, config).unwrap();
+        assert!(f.docs.contains_key("a") && f.docs.contains_key("c"));
+        assert!(!fs::read_to_string(&journal).unwrap().contains(r#""id":"b""#));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&journal);
+    });
+}